@@ -19,6 +19,41 @@ export interface ProductionsResult {
 
 export interface ProductionsRequestParams {
   test?: boolean;
+  folderUri?: string;
+}
+
+export type ProductionAction = 'start' | 'stop' | 'restart' | 'update';
+
+export interface ProductionActionOptions {
+  folderUri?: string;
+  /** Seconds to wait for business hosts to finish, 10 by default */
+  timeout?: number;
+  /** Stop business hosts that do not finish within the timeout */
+  force?: boolean;
+}
+
+export interface ProductionItemSetting {
+  name: string;
+  /** `Host` or `Adapter` */
+  target: string;
+  value?: string;
+  defaultValue?: string;
+}
+
+export interface ProductionItem {
+  name: string;
+  className: string;
+  category: string;
+  enabled: boolean;
+  poolSize?: number;
+  adapterClass?: string;
+  comment: string;
+  settings: ProductionItemSetting[];
+}
+
+export interface ProductionExportResult {
+  manifest: string;
+  files: string[];
 }
 
 export interface Global {
@@ -31,6 +66,91 @@ export interface GlobalsResult {
 
 export interface GlobalsRequestParams {
   test?: boolean;
+  folderUri?: string;
+}
+
+export interface CredentialsParams {
+  scopeUri?: string;
+  serverName?: string;
+  host: string;
+  port: number;
+  namespace: string;
+  username?: string;
+}
+
+export interface Credentials {
+  username: string;
+  password: string;
+}
+
+/** The `data` of an error response to the explorer requests. */
+export interface IrisErrorData {
+  kind:
+    | 'notConfigured'
+    | 'authFailed'
+    | 'network'
+    | 'namespaceNotFound'
+    | 'permissionDenied'
+    | 'sql'
+    | 'unsupported'
+    | 'server'
+    | 'unknownSetting';
+  message?: string;
+  namespace?: string;
+  sqlcode?: number;
+  operation?: string;
+  item?: string;
+  setting?: string;
+  known?: string[];
+}
+
+/**
+ * The roots of a tree: one per workspace folder, so that every folder is explored
+ * through its own connection, or a single one without a folder.
+ */
+export function workspaceRoots(label: string): { label: string; folderUri?: string }[] {
+  const folders = vscode.workspace.workspaceFolders ?? [];
+  if (folders.length <= 1) {
+    return [{ label, folderUri: folders[0]?.uri.toString() }];
+  }
+  return folders.map((folder) => ({ label: `${label} (${folder.name})`, folderUri: folder.uri.toString() }));
+}
+
+/** Turns `item` into a leaf that shows why the children of its parent could not be loaded. */
+export function showError(item: vscode.TreeItem, error: { message: string; data?: IrisErrorData }): void {
+  const kind = error.data?.kind;
+  item.label = kind === 'notConfigured' ? 'Not connected' : error.message;
+  item.tooltip = error.message;
+  item.iconPath = new vscode.ThemeIcon(
+    kind === 'network' || kind === 'notConfigured' ? 'debug-disconnect' : 'error',
+  );
+  item.contextValue = `error:${kind ?? 'unknown'}`;
+}
+
+/** Prompts for the password of a connection, and for the username when the settings have none. */
+export async function askCredentials(params: CredentialsParams): Promise<Credentials | null> {
+  const target = `${params.serverName ?? `${params.host}:${params.port}`}[${params.namespace}]`;
+  const username = params.username ?? await vscode.window.showInputBox({
+    prompt: `Username for ${target}`,
+    ignoreFocusOut: true,
+  });
+  if (!username) {
+    return null;
+  }
+  const password = await vscode.window.showInputBox({
+    prompt: `Password for ${username} on ${target}`,
+    password: true,
+    ignoreFocusOut: true,
+  });
+  return password === undefined ? null : { username, password };
+}
+
+export abstract class CredentialsRequest {
+  public static type = new RequestType<
+    CredentialsParams,
+    Credentials | null,
+    void
+  >('intersystems/credentials');
 }
 
 abstract class ProductionsRequest {
@@ -59,23 +179,61 @@ export class InterSystemsLanguageClient extends LanguageClient {
     this.registerProposedFeatures();
   }
 
-  public async productions(): Promise<ProductionsResult> {
-    return this.sendRequest(ProductionsRequest.type, {});
+  public async productions(folderUri?: string): Promise<ProductionsResult> {
+    return this.sendRequest(ProductionsRequest.type, { folderUri });
+  }
+
+  public async productionServices(id: String, folderUri?: string): Promise<ProductionsResult> {
+    return this.sendRequest("intersystems/productions/services", { id, folderUri });
   }
 
-  public async productionServices(id: String): Promise<ProductionsResult> {
-    return this.sendRequest("intersystems/productions/services", { id });
+  public async productionOperations(id: String, folderUri?: string): Promise<ProductionsResult> {
+    return this.sendRequest("intersystems/productions/operations", { id, folderUri });
+  }
+
+  public async productionProcesses(id: String, folderUri?: string): Promise<ProductionsResult> {
+    return this.sendRequest("intersystems/productions/processes", { id, folderUri });
+  }
+
+  public async productionItem(id: string, name: string, folderUri?: string): Promise<ProductionItem> {
+    return this.sendRequest("intersystems/productions/item", { id, name, folderUri });
+  }
+
+  public async enableProductionItem(
+    id: string,
+    name: string,
+    enabled: boolean,
+    options: ProductionActionOptions = {},
+  ): Promise<ProductionItem> {
+    const action = enabled ? 'enable' : 'disable';
+    return this.sendRequest(`intersystems/productions/item/${action}`, { id, name, ...options });
+  }
+
+  /** Changes a setting of a production item, or resets it to its default if `value` is undefined. */
+  public async setProductionItemSetting(
+    id: string,
+    name: string,
+    setting: ProductionItemSetting,
+    value: string | undefined,
+    options: ProductionActionOptions = {},
+  ): Promise<ProductionItem> {
+    const { target } = setting;
+    return this.sendRequest("intersystems/productions/item/setting", { id, name, target, setting: setting.name, value, ...options });
   }
 
-  public async productionOperations(id: String): Promise<ProductionsResult> {
-    return this.sendRequest("intersystems/productions/operations", { id });
+  public async controlProduction(
+    action: ProductionAction,
+    id: string,
+    options: ProductionActionOptions = {},
+  ): Promise<Production> {
+    return this.sendRequest(`intersystems/productions/${action}`, { id, ...options });
   }
 
-  public async productionProcesses(id: String): Promise<ProductionsResult> {
-    return this.sendRequest("intersystems/productions/processes", { id });
+  public async exportProduction(id: string, folderUri?: string, directory?: string): Promise<ProductionExportResult> {
+    return this.sendRequest("intersystems/productions/export", { id, folderUri, directory });
   }
 
-  public async globals(): Promise<GlobalsResult> {
-    return this.sendRequest(GlobalsRequest.type, {});
+  public async globals(folderUri?: string): Promise<GlobalsResult> {
+    return this.sendRequest(GlobalsRequest.type, { folderUri });
   }
 }
@@ -5,12 +5,12 @@ import {
   ServerOptions,
 } from 'vscode-languageclient/node';
 
-import { InteroperabilityNodeProvider } from "./interoperability/interoperabilityNodeProvider";
+import { InteroperabilityNodeProvider, ProductionBase, ProductionHost } from "./interoperability/interoperabilityNodeProvider";
 import { SystemExplorerNodeProvider } from "./system-explorer/systemExplorerNodeProvider";
 // import { AnalyticsNodeProvider } from "./analytics/analyticsNodeProvider";
 
 import { IRISWebView } from "./irisWebView";
-import { InterSystemsLanguageClient } from './client';
+import { askCredentials, CredentialsRequest, InterSystemsLanguageClient, ProductionAction } from './client';
 
 export function activate(context: vscode.ExtensionContext) {
   const serverConfig = vscode.workspace.getConfiguration('objectscript.server');
@@ -31,18 +31,73 @@ export function activate(context: vscode.ExtensionContext) {
     },
   );
   const interoperabiltyNodeProvider = new InteroperabilityNodeProvider(client);
+  const controlProduction = (action: ProductionAction) => (item: ProductionBase) =>
+    client.controlProduction(action, item.production, { folderUri: item.folderUri }).then(
+      () => interoperabiltyNodeProvider.refresh(),
+      (error) => vscode.window.showErrorMessage(error.message),
+    );
+  const enableProductionItem = (enabled: boolean) => (item: ProductionHost) =>
+    client.enableProductionItem(item.production, item.name, enabled, { folderUri: item.folderUri }).then(
+      () => interoperabiltyNodeProvider.refresh(),
+      (error) => vscode.window.showErrorMessage(error.message),
+    );
+  const changeProductionItemSetting = async (item: ProductionHost) => {
+    try {
+      const details = await client.productionItem(item.production, item.name, item.folderUri);
+      const picked = await vscode.window.showQuickPick(
+        details.settings.map((setting) => ({
+          label: setting.name,
+          description: setting.value ?? setting.defaultValue,
+          detail: setting.target,
+          setting,
+        })),
+        { placeHolder: `Setting of ${item.name}` },
+      );
+      if (!picked) {
+        return;
+      }
+      const { setting } = picked;
+      const value = await vscode.window.showInputBox({
+        prompt: `${setting.name} of ${item.name}, empty for the default ${setting.defaultValue ?? ''}`,
+        value: setting.value ?? '',
+      });
+      if (value === undefined) {
+        return;
+      }
+      const options = { folderUri: item.folderUri };
+      await client.setProductionItemSetting(item.production, item.name, setting, value === '' ? undefined : value, options);
+      interoperabiltyNodeProvider.refresh();
+    } catch (error) {
+      vscode.window.showErrorMessage(error.message);
+    }
+  };
   const systemExplorerNodeProvider = new SystemExplorerNodeProvider(client);
   // const analyticsNodeProvider = new AnalyticsNodeProvider(client);
 
   context.subscriptions.push(
     vscode.commands.registerCommand("intersystems.productions.refresh", () => interoperabiltyNodeProvider.refresh()),
-    vscode.commands.registerCommand("intersystems.productions.stop", () => vscode.window.showErrorMessage("Not Implemented")),
-    vscode.commands.registerCommand("intersystems.productions.start", () => vscode.window.showErrorMessage("Not Implemented")),
-    vscode.commands.registerCommand("intersystems.productions.restart", () => vscode.window.showErrorMessage("Not Implemented")),
+    vscode.commands.registerCommand("intersystems.productions.stop", controlProduction('stop')),
+    vscode.commands.registerCommand("intersystems.productions.start", controlProduction('start')),
+    vscode.commands.registerCommand("intersystems.productions.restart", controlProduction('restart')),
+    vscode.commands.registerCommand("intersystems.productions.update", controlProduction('update')),
+    vscode.commands.registerCommand("intersystems.productions.item.enable", enableProductionItem(true)),
+    vscode.commands.registerCommand("intersystems.productions.item.disable", enableProductionItem(false)),
+    vscode.commands.registerCommand("intersystems.productions.item.setting", changeProductionItemSetting),
     vscode.commands.registerCommand("intersystems.productions.open", (id) => IRISWebView.createOrShow(context.extensionUri, id)),
-    vscode.commands.registerCommand("intersystems.productions.export", () => vscode.window.showErrorMessage("Not Implemented")),
+    vscode.commands.registerCommand("intersystems.productions.export", (item: ProductionBase) =>
+      client.exportProduction(item.production, item.folderUri).then(
+        (result) => {
+          vscode.window.showInformationMessage(`Exported ${item.production} to ${result.files.length} files`);
+          return vscode.window.showTextDocument(vscode.Uri.parse(result.manifest));
+        },
+        (error) => vscode.window.showErrorMessage(error.message),
+      )),
 
     vscode.commands.registerCommand("intersystems.globals.refresh", () => systemExplorerNodeProvider.refresh()),
+    vscode.workspace.onDidChangeWorkspaceFolders(() => {
+      interoperabiltyNodeProvider.refresh();
+      systemExplorerNodeProvider.refresh();
+    }),
     vscode.commands.registerCommand("intersystems.globals.export", () => vscode.window.showErrorMessage("Not Implemented")),
     client.start(),
   );
@@ -50,6 +105,12 @@ export function activate(context: vscode.ExtensionContext) {
     vscode.window.createTreeView('intersystems-interoperability', { treeDataProvider: interoperabiltyNodeProvider, showCollapseAll: true });
     vscode.window.createTreeView('intersystems-system-explorer', { treeDataProvider: systemExplorerNodeProvider, showCollapseAll: true });
     // vscode.window.createTreeView('intersystems-analytics', { treeDataProvider: analyticsNodeProvider, showCollapseAll: true });
+    client.onRequest(CredentialsRequest.type, (params) => askCredentials(params));
+    client.onNotification('intersystems/refresh', () => {
+      interoperabiltyNodeProvider.refresh();
+      systemExplorerNodeProvider.refresh();
+    });
+    client.onNotification('intersystems/productionStatusChanged', () => interoperabiltyNodeProvider.refresh());
   })
 }
 
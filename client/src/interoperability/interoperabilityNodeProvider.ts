@@ -1,5 +1,5 @@
 import * as vscode from "vscode";
-import { InterSystemsLanguageClient } from '../client';
+import { InterSystemsLanguageClient, showError, workspaceRoots } from '../client';
 
 export class InteroperabilityBase extends vscode.TreeItem {
   constructor(public client: InterSystemsLanguageClient, label: string, collapsibleState?: vscode.TreeItemCollapsibleState) {
@@ -10,35 +10,53 @@ export class InteroperabilityBase extends vscode.TreeItem {
   }
 }
 
+export class InteroperabilityError extends InteroperabilityBase {
+  constructor(public client: InterSystemsLanguageClient, error) {
+    super(client, '');
+    showError(this, error);
+  }
+}
+
 export class InteroperabilityRoot extends InteroperabilityBase {
-  constructor(public client: InterSystemsLanguageClient, label: string) {
+  constructor(public client: InterSystemsLanguageClient, label: string, public folderUri?: string) {
     super(client, label, vscode.TreeItemCollapsibleState.Collapsed);
   }
 
   getChildren(): Thenable<InteroperabilityBase[]> {
-    return this.client.productions()
-      .then(result => result.list.map(el => new Production(this.client, el.id, el.status)));
+    return this.client.productions(this.folderUri)
+      .then(
+        result => result.list.map(el => new Production(this.client, el.id, el.status, this.folderUri)),
+        error => [new InteroperabilityError(this.client, error)],
+      );
   }
 
 }
 
 export class ProductionBase extends InteroperabilityBase {
-  constructor(public client: InterSystemsLanguageClient, public production: string, label: string, collapsibleState?: vscode.TreeItemCollapsibleState) {
+  constructor(
+    public client: InterSystemsLanguageClient,
+    public production: string,
+    label: string,
+    collapsibleState?: vscode.TreeItemCollapsibleState,
+    /** The workspace folder whose connection the production belongs to */
+    public folderUri?: string,
+  ) {
     super(client, label, collapsibleState);
   }
 
   getChildren(): Thenable<InteroperabilityBase[]> {
+    const collapsed = vscode.TreeItemCollapsibleState.Collapsed;
     return Promise.resolve([
-      new ProductionService(this.client, this.production, "Services", vscode.TreeItemCollapsibleState.Collapsed),
-      new ProductionProcess(this.client, this.production, "Processes", vscode.TreeItemCollapsibleState.Collapsed),
-      new ProductionOperation(this.client, this.production, "Operations", vscode.TreeItemCollapsibleState.Collapsed),
+      new ProductionService(this.client, this.production, "Services", collapsed, this.folderUri),
+      new ProductionProcess(this.client, this.production, "Processes", collapsed, this.folderUri),
+      new ProductionOperation(this.client, this.production, "Operations", collapsed, this.folderUri),
     ]);
   }
 }
 
 export class Production extends ProductionBase {
-  constructor(public client: InterSystemsLanguageClient, label: string, status: string) {
-    super(client, label, label, vscode.TreeItemCollapsibleState.Collapsed);
+  constructor(public client: InterSystemsLanguageClient, label: string, status: string, folderUri?: string) {
+    super(client, label, label, vscode.TreeItemCollapsibleState.Collapsed, folderUri);
     this.production = label;
     if ("Running" == status) {
       this.iconPath = new vscode.ThemeIcon("debug-start");
@@ -53,24 +71,41 @@ export class Production extends ProductionBase {
   }
 }
 
+/** A business host of a production, which has no children. */
+export class ProductionHost extends ProductionBase {
+  constructor(public client: InterSystemsLanguageClient, production: string, public name: string, folderUri?: string) {
+    super(client, production, name, undefined, folderUri);
+    this.contextValue = 'productionItem';
+  }
+}
+
 export class ProductionService extends ProductionBase {
-  getChildren(): Thenable<ProductionBase[]> {
-    return this.client.productionServices(this.production)
-      .then(el => el.list.map(el => new ProductionService(this.client, this.production, el.id)));
+  getChildren(): Thenable<InteroperabilityBase[]> {
+    return this.client.productionServices(this.production, this.folderUri)
+      .then(
+        el => el.list.map(el => new ProductionHost(this.client, this.production, el.id, this.folderUri)),
+        error => [new InteroperabilityError(this.client, error)],
+      );
   }
 }
 
 export class ProductionProcess extends ProductionBase {
-  getChildren(): Thenable<ProductionBase[]> {
-    return this.client.productionProcesses(this.production)
-      .then(el => el.list.map(el => new ProductionService(this.client, this.production, el.id)));
+  getChildren(): Thenable<InteroperabilityBase[]> {
+    return this.client.productionProcesses(this.production, this.folderUri)
+      .then(
+        el => el.list.map(el => new ProductionHost(this.client, this.production, el.id, this.folderUri)),
+        error => [new InteroperabilityError(this.client, error)],
+      );
   }
 }
 
 export class ProductionOperation extends ProductionBase {
-  getChildren(): Thenable<ProductionBase[]> {
-    return this.client.productionOperations(this.production)
-      .then(el => el.list.map(el => new ProductionService(this.client, this.production, el.id)));
+  getChildren(): Thenable<InteroperabilityBase[]> {
+    return this.client.productionOperations(this.production, this.folderUri)
+      .then(
+        el => el.list.map(el => new ProductionHost(this.client, this.production, el.id, this.folderUri)),
+        error => [new InteroperabilityError(this.client, error)],
+      );
   }
 }
 
@@ -93,9 +128,9 @@ export class InteroperabilityNodeProvider implements vscode.TreeDataProvider<Int
     if (element) {
       return element.getChildren();
     } else {
-      return Promise.resolve([
-        new InteroperabilityRoot(this.client, "Production")
-      ])
+      return Promise.resolve(
+        workspaceRoots("Production").map(({ label, folderUri }) => new InteroperabilityRoot(this.client, label, folderUri)),
+      );
     }
   }
 }
@@ -1,5 +1,5 @@
 import * as vscode from "vscode";
-import { InterSystemsLanguageClient } from '../client';
+import { InterSystemsLanguageClient, showError, workspaceRoots } from '../client';
 
 export class SystemExplorerNode extends vscode.TreeItem {
 
@@ -13,13 +13,23 @@ export class SystemExplorerNode extends vscode.TreeItem {
 }
 
 export class SystemExplorerRootNode extends SystemExplorerNode {
-  public constructor(client: InterSystemsLanguageClient, label: string) {
+  public constructor(client: InterSystemsLanguageClient, label: string, public folderUri?: string) {
     super(client, label, vscode.TreeItemCollapsibleState.Collapsed);
   }
 
   getChildren(element?: SystemExplorerNode): Thenable<SystemExplorerNode[]> {
-    return this.client.globals()
-      .then(result => result.list.map(el => new GlobalNode(this.client, el.name)));
+    return this.client.globals(this.folderUri)
+      .then(
+        result => result.list.map(el => new GlobalNode(this.client, el.name)),
+        error => [new SystemExplorerError(this.client, error)],
+      );
+  }
+}
+
+export class SystemExplorerError extends SystemExplorerNode {
+  public constructor(client: InterSystemsLanguageClient, error) {
+    super(client, '');
+    showError(this, error);
   }
 }
 
@@ -46,9 +56,9 @@ export class SystemExplorerNodeProvider implements vscode.TreeDataProvider<Syste
     if (element) {
       return element.getChildren();
     } else {
-      return Promise.resolve([
-        new SystemExplorerRootNode(this.client, "Globals")
-      ])
+      return Promise.resolve(
+        workspaceRoots("Globals").map(({ label, folderUri }) => new SystemExplorerRootNode(this.client, label, folderUri)),
+      );
     }
   }
 }
@@ -5,12 +5,18 @@ use futures::{
     channel::{mpsc, oneshot},
     prelude::*,
 };
+use log::{trace, warn};
 use serde::Serialize;
 use serde_json::json;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
 #[async_trait]
 pub trait ResponseHandler {
     async fn handle(&self, response: Response);
@@ -21,14 +27,20 @@ pub struct Client {
     output: mpsc::Sender<String>,
     request_id: AtomicU64,
     senders_by_id: CHashMap<Id, oneshot::Sender<Result<serde_json::Value>>>,
+    timeout: Option<Duration>,
 }
 
 impl Client {
     pub fn new(output: mpsc::Sender<String>) -> Self {
+        Self::with_timeout(output, Some(DEFAULT_REQUEST_TIMEOUT))
+    }
+
+    pub fn with_timeout(output: mpsc::Sender<String>, timeout: Option<Duration>) -> Self {
         Self {
             output,
             request_id: AtomicU64::new(0),
             senders_by_id: CHashMap::new(),
+            timeout,
         }
     }
 
@@ -37,14 +49,39 @@ impl Client {
         method: String,
         params: T,
     ) -> Result<serde_json::Value> {
-        let id = self.request_id.fetch_add(1, Ordering::SeqCst);
-        let request = Request::new(method, json!(params), Id::Number(id));
+        self.send_request_with_timeout(method, params, self.timeout)
+            .await
+    }
+
+    pub async fn send_request_with_timeout<T: Serialize>(
+        &self,
+        method: String,
+        params: T,
+        timeout: Option<Duration>,
+    ) -> Result<serde_json::Value> {
+        let id = Id::Number(self.request_id.fetch_add(1, Ordering::SeqCst));
+        let request = Request::new(method.clone(), json!(params), id.clone());
 
         let (result_tx, result_rx) = oneshot::channel();
-        self.senders_by_id.insert(request.id.clone(), result_tx);
-        self.send(Message::Request(request)).await;
+        self.senders_by_id.insert(id.clone(), result_tx);
+        if !self.send(Message::Request(request)).await {
+            self.senders_by_id.remove(&id);
+            return Err(Error::request_dropped_error(&method));
+        }
 
-        result_rx.await.unwrap()
+        let result = match timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, result_rx).await {
+                Ok(result) => result,
+                Err(_) => {
+                    self.senders_by_id.remove(&id);
+                    self.send_notification("$/cancelRequest".to_owned(), CancelParams { id })
+                        .await;
+                    return Err(Error::request_timeout_error(&method, timeout));
+                }
+            },
+            None => result_rx.await,
+        };
+        result.unwrap_or_else(|_| Err(Error::request_dropped_error(&method)))
     }
 
     pub async fn send_notification<T: Serialize>(&self, method: String, params: T) {
@@ -52,26 +89,109 @@ impl Client {
         self.send(Message::Notification(notification)).await;
     }
 
-    async fn send(&self, message: Message) {
+    async fn send(&self, message: Message) -> bool {
         let mut output = self.output.clone();
         let json = serde_json::to_string(&message).unwrap();
-        output.send(json).await.unwrap();
+        match output.send(json).await {
+            Ok(()) => true,
+            Err(why) => {
+                warn!("Failed to send message: {}", why);
+                false
+            }
+        }
     }
 }
 
 #[async_trait]
 impl ResponseHandler for Client {
     async fn handle(&self, response: Response) {
-        let id = response.id.expect("Expected response with id");
+        let id = match response.id {
+            Some(id) => id,
+            None => {
+                warn!("Ignoring response without id: {:?}", response.error);
+                return;
+            }
+        };
+
         let result = match response.error {
             Some(why) => Err(why),
             None => Ok(response.result.unwrap_or(serde_json::Value::Null)),
         };
 
-        let result_tx = self
-            .senders_by_id
-            .remove(&id)
-            .expect("Unexpected response received");
-        result_tx.send(result).unwrap();
+        match self.senders_by_id.remove(&id) {
+            Some(result_tx) => {
+                if result_tx.send(result).is_err() {
+                    trace!("Response {:?} is not awaited anymore", id);
+                }
+            }
+            None => warn!("Ignoring unexpected response: {:?}", id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup_client(timeout: Option<Duration>) -> (Client, mpsc::Receiver<String>) {
+        let (output_tx, output_rx) = mpsc::channel(16);
+        (Client::with_timeout(output_tx, timeout), output_rx)
+    }
+
+    async fn next_message(output_rx: &mut mpsc::Receiver<String>) -> Message {
+        let json = output_rx.next().await.unwrap();
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[tokio::test]
+    async fn request_valid() {
+        let (client, mut output_rx) = setup_client(None);
+        let request = client.send_request("foo".to_owned(), 42);
+        let respond = async {
+            match next_message(&mut output_rx).await {
+                Message::Request(request) => {
+                    client
+                        .handle(Response::result(json!("bar"), request.id))
+                        .await
+                }
+                message => panic!("unexpected message: {:?}", message),
+            }
+        };
+
+        let (result, _) = future::join(request, respond).await;
+        assert_eq!(result, Ok(json!("bar")));
+    }
+
+    #[tokio::test]
+    async fn request_timeout() {
+        let (client, mut output_rx) = setup_client(Some(Duration::from_millis(10)));
+        let result = client.send_request("foo".to_owned(), ()).await;
+        assert_eq!(result.unwrap_err().code, ErrorCode::RequestTimeout);
+
+        assert!(matches!(next_message(&mut output_rx).await, Message::Request(_)));
+        match next_message(&mut output_rx).await {
+            Message::Notification(notification) => {
+                assert_eq!(notification.method, "$/cancelRequest");
+                assert_eq!(notification.params, Some(json!({ "id": 0 })));
+            }
+            message => panic!("unexpected message: {:?}", message),
+        }
+        assert!(client.senders_by_id.is_empty());
+    }
+
+    #[tokio::test]
+    async fn request_output_closed() {
+        let (client, output_rx) = setup_client(None);
+        drop(output_rx);
+
+        let result = client.send_request("foo".to_owned(), ()).await;
+        assert_eq!(result.unwrap_err().code, ErrorCode::RequestCancelled);
+    }
+
+    #[tokio::test]
+    async fn response_unknown_id() {
+        let (client, _output_rx) = setup_client(None);
+        client.handle(Response::result(json!(null), Id::Number(42))).await;
+        client.handle(Response::error(Error::parse_error(), None)).await;
     }
 }
@@ -1,22 +1,40 @@
 pub mod client;
+pub mod scheduler;
 pub mod server;
 mod types;
 
 pub use self::{
     client::{Client, ResponseHandler},
-    server::{handle_notification, handle_request, Middleware, RequestHandler},
+    scheduler::Scheduler,
+    server::{
+        handle_notification, handle_notification_or, handle_request, handle_request_or,
+        DefaultParams, DeserializedParams, Middleware, OmittedParams, RequestHandler,
+    },
     types::*,
 };
 
-use futures::{channel::mpsc, prelude::*};
-use log::{error,trace};
+use chashmap::CHashMap;
+use futures::{
+    channel::mpsc,
+    future::{self, AbortHandle, Aborted},
+    prelude::*,
+};
+use log::{error, trace, warn};
 use std::sync::Arc;
 
+const CANCEL_REQUEST_METHOD: &str = "$/cancelRequest";
+const EXIT_METHOD: &str = "exit";
+
 #[derive(Debug)]
 pub struct MessageHandler<S, C> {
     pub server: Arc<S>,
     pub client: Arc<C>,
     pub output: mpsc::Sender<String>,
+    /// The abort handles of the running requests, each with a number that tells it apart
+    /// from a later request with the same id
+    pending_requests: Arc<CHashMap<Id, (u64, AbortHandle)>>,
+    dispatched_requests: u64,
+    scheduler: Scheduler,
 }
 
 impl<S, C> MessageHandler<S, C>
@@ -24,39 +42,179 @@ where
     S: RequestHandler + Middleware + Send + Sync + 'static,
     C: ResponseHandler + Send + Sync + 'static,
 {
+    pub fn new(server: Arc<S>, client: Arc<C>, output: mpsc::Sender<String>) -> Self {
+        Self::with_max_concurrent_requests(
+            server,
+            client,
+            output,
+            scheduler::DEFAULT_MAX_CONCURRENT_REQUESTS,
+        )
+    }
+
+    pub fn with_max_concurrent_requests(
+        server: Arc<S>,
+        client: Arc<C>,
+        output: mpsc::Sender<String>,
+        max_concurrent_requests: usize,
+    ) -> Self {
+        Self {
+            server,
+            client,
+            output,
+            pending_requests: Arc::new(CHashMap::new()),
+            dispatched_requests: 0,
+            scheduler: Scheduler::new(max_concurrent_requests),
+        }
+    }
+
     pub async fn handle(&mut self, json: &str) {
         trace!("handle message: {:?}", json);
         self.server.before_message().await;
 
-        match serde_json::from_str(json).map_err(|_| Error::parse_error()) {
-            Ok(Message::Request(request)) => {
-                let server = Arc::clone(&self.server);
-                let mut output = self.output.clone();
-                tokio::spawn(async move {
-                    let response = server.handle_request(request).await;
-                    if let Some(error) = response.error.as_ref() {
-                        error!("{:?}", error);
-                    }
-                    let json = serde_json::to_string(&response).unwrap();
-                    output.send(json).await.unwrap();
-                    server.after_message().await;
-                });
+        match serde_json::from_str(json) {
+            Ok(serde_json::Value::Array(batch)) if !batch.is_empty() => {
+                self.handle_batch(batch).await;
             }
-            Ok(Message::Notification(notification)) => {
-                self.server.handle_notification(notification).await;
-                self.after_message();
+            Ok(json) => match serde_json::from_value(json) {
+                Ok(Message::Request(request)) => {
+                    let response = self.dispatch_request(request);
+                    let server = Arc::clone(&self.server);
+                    let mut output = self.output.clone();
+                    tokio::spawn(async move {
+                        let json = serde_json::to_string(&response.await).unwrap();
+                        output.send(json).await.unwrap();
+                        server.after_message().await;
+                    });
+                }
+                Ok(Message::Notification(notification)) => {
+                    self.dispatch_notification(notification).await;
+                }
+                Ok(Message::Response(response)) => {
+                    self.client.handle(response).await;
+                    self.after_message();
+                }
+                Err(_) => {
+                    self.send_error(Error::invalid_request_error("Invalid message".to_owned()))
+                        .await;
+                }
+            },
+            Err(_) => self.send_error(Error::parse_error()).await,
+        };
+    }
+
+    async fn handle_batch(&mut self, batch: Vec<serde_json::Value>) {
+        let mut responses = Vec::new();
+        for json in batch {
+            match serde_json::from_value(json) {
+                Ok(Message::Request(request)) => {
+                    responses.push(self.dispatch_request(request).boxed());
+                }
+                Ok(Message::Notification(notification)) => {
+                    self.dispatch_notification(notification).await;
+                }
+                Ok(Message::Response(response)) => self.client.handle(response).await,
+                Err(_) => {
+                    let error = Error::invalid_request_error("Invalid message".to_owned());
+                    responses.push(future::ready(Response::error(error, None)).boxed());
+                }
             }
-            Ok(Message::Response(response)) => {
-                self.client.handle(response).await;
-                self.after_message();
+        }
+
+        let server = Arc::clone(&self.server);
+        let mut output = self.output.clone();
+        tokio::spawn(async move {
+            let responses = future::join_all(responses).await;
+            if !responses.is_empty() {
+                let json = serde_json::to_string(&responses).unwrap();
+                output.send(json).await.unwrap();
             }
-            Err(why) => {
-                let response = Response::error(why, None);
-                let json = serde_json::to_string(&response).unwrap();
-                self.output.send(json).await.unwrap();
-                self.after_message();
+            server.after_message().await;
+        });
+    }
+
+    async fn dispatch_notification(&mut self, notification: Notification) {
+        let server = Arc::clone(&self.server);
+        if notification.method == CANCEL_REQUEST_METHOD {
+            self.cancel_request(notification);
+            self.after_message();
+        } else if is_barrier(&notification.method) {
+            let task = self.scheduler.barrier(async move {
+                Self::handle_notification(&server, notification).await;
+                server.after_message().await;
+            });
+            tokio::spawn(task);
+        } else {
+            Self::handle_notification(&server, notification).await;
+            self.after_message();
+        }
+    }
+
+    async fn handle_notification(server: &S, notification: Notification) {
+        if server.before_notification(&notification).await {
+            let method = notification.method.clone();
+            if let Err(error) = server.handle_notification(notification).await {
+                error!("{}: {:?}", method, error);
+                server.notification_failed(&method, error).await;
             }
-        };
+        } else {
+            trace!("dropped notification: {}", notification.method);
+        }
+    }
+
+    fn dispatch_request(&mut self, request: Request) -> impl Future<Output = Response> + Send {
+        let server = Arc::clone(&self.server);
+        let pending_requests = Arc::clone(&self.pending_requests);
+        let id = request.id.clone();
+        let method = request.method.clone();
+        let middleware = Arc::clone(&self.server);
+        let (task, abort_handle) = future::abortable(self.scheduler.concurrent(async move {
+            match server.before_request(&request).await {
+                Ok(()) => server.handle_request(request).await,
+                Err(error) => Response::error(error, Some(request.id)),
+            }
+        }));
+        self.dispatched_requests += 1;
+        let number = self.dispatched_requests;
+        self.pending_requests
+            .insert(id.clone(), (number, abort_handle));
+
+        async move {
+            let response = match task.await {
+                Ok(response) => response,
+                Err(Aborted) => Response::error(Error::request_cancelled_error(), Some(id.clone())),
+            };
+            // The id may have been reused by a request that is still running
+            pending_requests.alter(id.clone(), |entry| {
+                entry.filter(|(pending, _)| *pending != number)
+            });
+            middleware.after_request(&method, &response).await;
+
+            if let Some(error) = response.error.as_ref() {
+                error!("{:?}", error);
+            }
+            response
+        }
+    }
+
+    async fn send_error(&mut self, error: Error) {
+        let response = Response::error(error, None);
+        let json = serde_json::to_string(&response).unwrap();
+        self.output.send(json).await.unwrap();
+        self.after_message();
+    }
+
+    fn cancel_request(&self, notification: Notification) {
+        let params = notification.params.unwrap_or_default();
+        match serde_json::from_value::<CancelParams>(params) {
+            Ok(params) => match self.pending_requests.remove(&params.id) {
+                Some((_, abort_handle)) => {
+                    trace!("cancel request: {:?}", params.id);
+                    abort_handle.abort();
+                }
+                None => trace!("request {:?} is not running anymore", params.id),
+            },
+            Err(why) => warn!("Invalid cancel request: {}", why),
+        }
     }
 
     fn after_message(&self) {
@@ -66,3 +224,226 @@ where
         });
     }
 }
+
+/// Notifications change the server state, so requests that arrive after them wait until
+/// they are handled, see [`Scheduler`]. Protocol notifications like `$/setTrace` and `exit`
+/// are handled right away.
+fn is_barrier(method: &str) -> bool {
+    !method.starts_with("$/") && method != EXIT_METHOD
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[derive(Default)]
+    struct PendingServer {
+        finished: std::sync::Mutex<Vec<(String, Response)>>,
+    }
+
+    #[async_trait]
+    impl RequestHandler for PendingServer {
+        async fn handle_request(&self, request: Request) -> Response {
+            match request.method.as_str() {
+                "pending" => future::pending::<()>().await,
+                "delayed" => tokio::time::delay_for(Duration::from_millis(50)).await,
+                _ => {}
+            }
+            Response::result(json!(request.method), request.id)
+        }
+
+        async fn handle_notification(
+            &self,
+            _notification: Notification,
+        ) -> std::result::Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl Middleware for PendingServer {
+        async fn before_message(&self) {}
+
+        async fn after_message(&self) {}
+
+        async fn before_request(&self, request: &Request) -> std::result::Result<(), Error> {
+            if request.method == "rejected" {
+                Err(Error::server_not_initialized_error())
+            } else {
+                Ok(())
+            }
+        }
+
+        async fn after_request(&self, method: &str, response: &Response) {
+            let finished = (method.to_owned(), response.clone());
+            self.finished.lock().unwrap().push(finished);
+        }
+    }
+
+    struct NoopClient;
+
+    #[async_trait]
+    impl ResponseHandler for NoopClient {
+        async fn handle(&self, _response: Response) {}
+    }
+
+    fn setup_handler() -> (
+        MessageHandler<PendingServer, NoopClient>,
+        mpsc::Receiver<String>,
+    ) {
+        let (output_tx, output_rx) = mpsc::channel(16);
+        let handler = MessageHandler::new(
+            Arc::new(PendingServer::default()),
+            Arc::new(NoopClient),
+            output_tx,
+        );
+        (handler, output_rx)
+    }
+
+    async fn next_response(output_rx: &mut mpsc::Receiver<String>) -> Response {
+        let json = output_rx.next().await.unwrap();
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[tokio::test]
+    async fn cancel_pending_request() {
+        let (mut handler, mut output_rx) = setup_handler();
+        let request = Request::new("pending".into(), json!(null), Id::Number(1));
+        handler.handle(&json!(request).to_string()).await;
+
+        let cancel = Notification::new(CANCEL_REQUEST_METHOD.into(), json!({ "id": 1 }));
+        handler.handle(&json!(cancel).to_string()).await;
+
+        let response = next_response(&mut output_rx).await;
+        assert_eq!(
+            response,
+            Response::error(Error::request_cancelled_error(), Some(Id::Number(1)))
+        );
+        assert!(handler.pending_requests.is_empty());
+        assert_eq!(
+            *handler.server.finished.lock().unwrap(),
+            vec![(String::from("pending"), response)]
+        );
+    }
+
+    #[tokio::test]
+    async fn cancel_request_with_reused_id() {
+        let (mut handler, mut output_rx) = setup_handler();
+        let delayed = Request::new("delayed".into(), json!(null), Id::Number(1));
+        handler.handle(&json!(delayed).to_string()).await;
+        let pending = Request::new("pending".into(), json!(null), Id::Number(1));
+        handler.handle(&json!(pending).to_string()).await;
+
+        let response = next_response(&mut output_rx).await;
+        assert_eq!(response, Response::result(json!("delayed"), Id::Number(1)));
+
+        let cancel = Notification::new(CANCEL_REQUEST_METHOD.into(), json!({ "id": 1 }));
+        handler.handle(&json!(cancel).to_string()).await;
+        let response = next_response(&mut output_rx).await;
+        assert_eq!(
+            response,
+            Response::error(Error::request_cancelled_error(), Some(Id::Number(1)))
+        );
+    }
+
+    #[tokio::test]
+    async fn cancel_unknown_request() {
+        let (mut handler, mut output_rx) = setup_handler();
+        let cancel = Notification::new(CANCEL_REQUEST_METHOD.into(), json!({ "id": "foo" }));
+        handler.handle(&json!(cancel).to_string()).await;
+
+        let request = Request::new("bar".into(), json!(null), Id::Number(2));
+        handler.handle(&json!(request).to_string()).await;
+
+        let response = next_response(&mut output_rx).await;
+        assert_eq!(response, Response::result(json!("bar"), Id::Number(2)));
+    }
+
+    #[tokio::test]
+    async fn reject_request_before_handling() {
+        let (mut handler, mut output_rx) = setup_handler();
+        let request = Request::new("rejected".into(), json!(null), Id::Number(3));
+        handler.handle(&json!(request).to_string()).await;
+
+        let response = next_response(&mut output_rx).await;
+        assert_eq!(
+            response,
+            Response::error(Error::server_not_initialized_error(), Some(Id::Number(3)))
+        );
+        assert_eq!(
+            *handler.server.finished.lock().unwrap(),
+            vec![(String::from("rejected"), response)]
+        );
+    }
+
+    #[tokio::test]
+    async fn batch_requests() {
+        let (mut handler, mut output_rx) = setup_handler();
+        let batch = json!([
+            Request::new("foo".into(), json!(null), Id::Number(1)),
+            Notification::new("bar".into(), json!(null)),
+            Request::new("baz".into(), json!(null), Id::Number(2)),
+        ]);
+        handler.handle(&batch.to_string()).await;
+
+        let json = output_rx.next().await.unwrap();
+        let responses: Vec<Response> = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            responses,
+            vec![
+                Response::result(json!("foo"), Id::Number(1)),
+                Response::result(json!("baz"), Id::Number(2)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn batch_invalid_element() {
+        let (mut handler, mut output_rx) = setup_handler();
+        let batch = json!([42, Request::new("foo".into(), json!(null), Id::Number(1))]);
+        handler.handle(&batch.to_string()).await;
+
+        let json = output_rx.next().await.unwrap();
+        let responses: Vec<Response> = serde_json::from_str(&json).unwrap();
+        assert_eq!(responses.len(), 2);
+        assert_eq!(
+            responses[0].error.as_ref().unwrap().code,
+            ErrorCode::InvalidRequest
+        );
+        assert_eq!(responses[1], Response::result(json!("foo"), Id::Number(1)));
+    }
+
+    #[tokio::test]
+    async fn batch_empty() {
+        let (mut handler, mut output_rx) = setup_handler();
+        handler.handle("[]").await;
+
+        let response = next_response(&mut output_rx).await;
+        assert_eq!(response.error.unwrap().code, ErrorCode::InvalidRequest);
+    }
+
+    #[tokio::test]
+    async fn batch_notifications_only() {
+        let (mut handler, mut output_rx) = setup_handler();
+        let batch = json!([Notification::new("bar".into(), json!(null))]);
+        handler.handle(&batch.to_string()).await;
+
+        let request = Request::new("foo".into(), json!(null), Id::Number(1));
+        handler.handle(&json!(request).to_string()).await;
+
+        let response = next_response(&mut output_rx).await;
+        assert_eq!(response, Response::result(json!("foo"), Id::Number(1)));
+    }
+
+    #[tokio::test]
+    async fn invalid_json() {
+        let (mut handler, mut output_rx) = setup_handler();
+        handler.handle("{").await;
+
+        let response = next_response(&mut output_rx).await;
+        assert_eq!(response, Response::error(Error::parse_error(), None));
+    }
+}
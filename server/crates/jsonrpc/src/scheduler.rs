@@ -0,0 +1,151 @@
+use futures::{
+    channel::oneshot,
+    future::{self, BoxFuture, Shared},
+    prelude::*,
+};
+use std::{mem, sync::Arc};
+use tokio::sync::Semaphore;
+
+pub const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 8;
+
+type Completion = Shared<BoxFuture<'static, ()>>;
+
+/// Decides when incoming messages are allowed to run.
+///
+/// Messages are scheduled in the order in which they arrive:
+///
+/// - A concurrent task starts as soon as every barrier scheduled before it has finished
+///   and one of the `max_concurrent_requests` slots is free.
+/// - A barrier starts as soon as every barrier scheduled before it has finished
+///   and delays every task scheduled after it until it has finished itself.
+///
+/// A barrier does not wait for the concurrent tasks scheduled before it, so a slow request
+/// never holds up a change of state. Those that have not started yet see its changes.
+///
+/// A task that is dropped before it completes (e.g. a cancelled request) counts as finished.
+#[derive(Debug)]
+pub struct Scheduler {
+    semaphore: Arc<Semaphore>,
+    barrier: Completion,
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_CONCURRENT_REQUESTS)
+    }
+}
+
+impl Scheduler {
+    pub fn new(max_concurrent_requests: usize) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(max_concurrent_requests.max(1))),
+            barrier: future::ready(()).boxed().shared(),
+        }
+    }
+
+    pub fn concurrent<F>(&mut self, task: F) -> impl Future<Output = F::Output> + Send
+    where
+        F: Future + Send,
+    {
+        let barrier = self.barrier.clone();
+        let semaphore = Arc::clone(&self.semaphore);
+
+        async move {
+            barrier.await;
+            let _permit = semaphore.acquire().await;
+            task.await
+        }
+    }
+
+    pub fn barrier<F>(&mut self, task: F) -> impl Future<Output = F::Output> + Send
+    where
+        F: Future + Send,
+    {
+        let (done_tx, done_rx) = oneshot::channel::<()>();
+        let completion = done_rx.map(|_| ()).boxed().shared();
+        let previous = mem::replace(&mut self.barrier, completion);
+
+        async move {
+            previous.await;
+            let output = task.await;
+            drop(done_tx);
+            output
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::lock::Mutex;
+    use std::time::Duration;
+
+    async fn record(log: Arc<Mutex<Vec<&'static str>>>, entry: &'static str, delay: u64) {
+        tokio::time::delay_for(Duration::from_millis(delay)).await;
+        log.lock().await.push(entry);
+    }
+
+    #[tokio::test]
+    async fn tasks_wait_for_earlier_barriers() {
+        let log = Arc::default();
+        let mut scheduler = Scheduler::default();
+        let first = tokio::spawn(scheduler.barrier(record(Arc::clone(&log), "first", 50)));
+        let second = tokio::spawn(scheduler.barrier(record(Arc::clone(&log), "second", 0)));
+        let task = tokio::spawn(scheduler.concurrent(record(Arc::clone(&log), "task", 0)));
+
+        let _ = future::join3(first, second, task).await;
+        assert_eq!(*log.lock().await, vec!["first", "second", "task"]);
+    }
+
+    #[tokio::test]
+    async fn barrier_does_not_wait_for_earlier_tasks() {
+        let log = Arc::default();
+        let mut scheduler = Scheduler::default();
+        let (pending, abort_handle) =
+            future::abortable(scheduler.concurrent(future::pending::<()>()));
+        tokio::spawn(pending);
+        tokio::time::delay_for(Duration::from_millis(10)).await;
+        let barrier = scheduler.barrier(record(Arc::clone(&log), "barrier", 0));
+
+        tokio::time::timeout(Duration::from_secs(1), barrier)
+            .await
+            .unwrap();
+        assert_eq!(*log.lock().await, vec!["barrier"]);
+        abort_handle.abort();
+    }
+
+    #[tokio::test]
+    async fn concurrent_tasks_overlap() {
+        let log = Arc::default();
+        let mut scheduler = Scheduler::new(2);
+        let slow = tokio::spawn(scheduler.concurrent(record(Arc::clone(&log), "slow", 50)));
+        let fast = tokio::spawn(scheduler.concurrent(record(Arc::clone(&log), "fast", 0)));
+
+        let _ = future::join(slow, fast).await;
+        assert_eq!(*log.lock().await, vec!["fast", "slow"]);
+    }
+
+    #[tokio::test]
+    async fn concurrent_tasks_limit() {
+        let log = Arc::default();
+        let mut scheduler = Scheduler::new(1);
+        let slow = tokio::spawn(scheduler.concurrent(record(Arc::clone(&log), "slow", 50)));
+        tokio::time::delay_for(Duration::from_millis(10)).await;
+        let fast = tokio::spawn(scheduler.concurrent(record(Arc::clone(&log), "fast", 0)));
+
+        let _ = future::join(slow, fast).await;
+        assert_eq!(*log.lock().await, vec!["slow", "fast"]);
+    }
+
+    #[tokio::test]
+    async fn dropped_barrier_releases_tasks() {
+        let log = Arc::default();
+        let mut scheduler = Scheduler::default();
+        let pending = scheduler.barrier(future::pending::<()>());
+        let task = scheduler.concurrent(record(Arc::clone(&log), "task", 0));
+        drop(pending);
+
+        task.await;
+        assert_eq!(*log.lock().await, vec!["task"]);
+    }
+}
@@ -3,14 +3,16 @@ use async_trait::async_trait;
 use futures::prelude::*;
 use serde::{de::DeserializeOwned, Serialize};
 use serde_json::json;
+use std::marker::PhantomData;
 
-pub type Result<T> = std::result::Result<T, String>;
+pub type Result<T> = std::result::Result<T, Error>;
 
 #[async_trait]
 pub trait RequestHandler {
     async fn handle_request(&self, request: Request) -> Response;
 
-    async fn handle_notification(&self, notification: Notification);
+    async fn handle_notification(&self, notification: Notification)
+        -> std::result::Result<(), Error>;
 }
 
 #[async_trait]
@@ -18,6 +20,20 @@ pub trait Middleware {
     async fn before_message(&self);
 
     async fn after_message(&self);
+
+    async fn before_request(&self, _request: &Request) -> std::result::Result<(), Error> {
+        Ok(())
+    }
+
+    /// Called with the response of every request, also when it was rejected,
+    /// its params were invalid or it was cancelled.
+    async fn after_request(&self, _method: &str, _response: &Response) {}
+
+    async fn before_notification(&self, _notification: &Notification) -> bool {
+        true
+    }
+
+    async fn notification_failed(&self, _method: &str, _error: Error) {}
 }
 
 pub async fn handle_request<'a, H, F, I, O>(request: Request, handler: H) -> Response
@@ -26,33 +42,127 @@ where
     F: Future<Output = Result<O>> + Send,
     I: DeserializeOwned + Send,
     O: Serialize,
+{
+    handle_request_or(request, handler, || deserialize_params(None)).await
+}
+
+/// Like [`handle_request`], but `omitted` makes the params if the request has none.
+pub async fn handle_request_or<'a, H, F, I, O, P>(
+    request: Request,
+    handler: H,
+    omitted: P,
+) -> Response
+where
+    H: Fn(I) -> F + Send + Sync + 'a,
+    F: Future<Output = Result<O>> + Send,
+    I: DeserializeOwned + Send,
+    O: Serialize,
+    P: FnOnce() -> Result<I> + Send,
 {
     let handle = |json| async move {
-        let params: I = serde_json::from_value(json).map_err(|_| Error::deserialize_error())?;
-        let result = handler(params).await.map_err(Error::internal_error)?;
+        let params: I = match json {
+            Some(json) => deserialize_params(Some(json))?,
+            None => omitted()?,
+        };
+        let result = handler(params).await?;
         Ok(result)
     };
 
-    match handle(request.params).await {
+    match handle(request.params.filter(|params| !params.is_null())).await {
         Ok(result) => Response::result(json!(result), request.id),
         Err(error) => Response::error(error, Some(request.id)),
     }
 }
 
-pub async fn handle_notification<'a, H, F, I>(notification: Notification, handler: H)
+pub async fn handle_notification<'a, H, F, I>(
+    notification: Notification,
+    handler: H,
+) -> std::result::Result<(), Error>
+where
+    H: Fn(I) -> F + Send + Sync + 'a,
+    F: Future<Output = ()> + Send,
+    I: DeserializeOwned + Send,
+{
+    handle_notification_or(notification, handler, || deserialize_params(None)).await
+}
+
+/// Like [`handle_notification`], but `omitted` makes the params if the notification has none.
+pub async fn handle_notification_or<'a, H, F, I, P>(
+    notification: Notification,
+    handler: H,
+    omitted: P,
+) -> std::result::Result<(), Error>
 where
     H: Fn(I) -> F + Send + Sync + 'a,
     F: Future<Output = ()> + Send,
     I: DeserializeOwned + Send,
+    P: FnOnce() -> Result<I> + Send,
 {
-    let error = Error::deserialize_error().message;
-    let params = serde_json::from_value(notification.params).expect(&error);
+    let params = match notification.params.filter(|params| !params.is_null()) {
+        Some(params) => deserialize_params(Some(params))?,
+        None => omitted()?,
+    };
     handler(params).await;
+    Ok(())
+}
+
+/// The params of a method that were left out: the `Default` of the params type, such as `()`
+/// or a struct with only optional fields, and otherwise what `null` or `{}` deserializes to.
+///
+/// The code generated for a method calls `(&OmittedParams::<T>::new()).value()` with
+/// [`DefaultParams`] and [`DeserializedParams`] in scope. Where `T: Default`, the method of
+/// `DefaultParams` matches the receiver as it is and wins over that of `DeserializedParams`,
+/// which needs another reference.
+pub struct OmittedParams<T>(PhantomData<T>);
+
+impl<T> OmittedParams<T> {
+    pub fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<T> Default for OmittedParams<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub trait DefaultParams<T> {
+    fn value(&self) -> Result<T>;
+}
+
+impl<T: Default> DefaultParams<T> for OmittedParams<T> {
+    fn value(&self) -> Result<T> {
+        Ok(T::default())
+    }
+}
+
+pub trait DeserializedParams<T> {
+    fn value(&self) -> Result<T>;
+}
+
+impl<T: DeserializeOwned> DeserializedParams<T> for &OmittedParams<T> {
+    fn value(&self) -> Result<T> {
+        deserialize_params(None)
+    }
+}
+
+fn deserialize_params<I: DeserializeOwned>(
+    params: Option<serde_json::Value>,
+) -> std::result::Result<I, Error> {
+    let result = match params.filter(|params| !params.is_null()) {
+        Some(params) => serde_json::from_value(params),
+        // Omitted params map onto `()` or onto a struct whose fields are all optional
+        None => serde_json::from_value(serde_json::Value::Null)
+            .or_else(|_| serde_json::from_value(json!({}))),
+    };
+    result.map_err(|why| Error::deserialize_error().with_data(json!(why.to_string())))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use serde::Deserialize;
 
     const METHOD_NAME: &str = "foo";
 
@@ -60,14 +170,43 @@ mod tests {
         Ok(i + 1)
     }
 
+    async fn reject(_params: ()) -> Result<()> {
+        Err(Error::new(ErrorCode::Custom(1000), "rejected".to_owned()).with_data(json!(42)))
+    }
+
     async fn panic(_params: ()) {
         panic!("success");
     }
 
+    #[derive(Debug, Deserialize)]
+    struct OptionalParams {
+        value: Option<i32>,
+    }
+
+    async fn unwrap_or_zero(params: OptionalParams) -> Result<i32> {
+        Ok(params.value.unwrap_or_default())
+    }
+
+    #[derive(Debug, Default, Deserialize)]
+    struct DefaultedParams {
+        value: i32,
+    }
+
+    async fn value(params: DefaultedParams) -> Result<i32> {
+        Ok(params.value)
+    }
+
+    /// What `jsonrpc_server` generates for methods with params of type `$ty`.
+    macro_rules! omitted {
+        ($ty:ty) => {
+            || (&OmittedParams::<$ty>::new()).value()
+        };
+    }
+
     fn setup_request<T: Serialize>(value: T) -> Request {
         Request {
             jsonrpc: PROTOCOL_VERSION.to_owned(),
-            params: json!(value),
+            params: Some(json!(value)),
             method: METHOD_NAME.to_owned(),
             id: Id::Number(0),
         }
@@ -77,7 +216,7 @@ mod tests {
         Notification {
             jsonrpc: PROTOCOL_VERSION.to_owned(),
             method: METHOD_NAME.to_owned(),
-            params: json!(()),
+            params: None,
         }
     }
 
@@ -105,27 +244,98 @@ mod tests {
         let expected = Response {
             jsonrpc: request.jsonrpc.clone(),
             result: None,
-            error: Some(Error::deserialize_error()),
+            error: Some(Error::deserialize_error().with_data(json!(
+                "invalid type: sequence, expected i32"
+            ))),
             id: Some(request.id),
         };
 
         assert_eq!(response, expected);
     }
 
+    #[tokio::test]
+    async fn request_omitted_params() {
+        let request = Request {
+            params: None,
+            ..setup_request(())
+        };
+
+        let response = handle_request(request.clone(), unwrap_or_zero).await;
+        assert_eq!(response, Response::result(json!(0), request.id));
+    }
+
+    #[tokio::test]
+    async fn request_null_params() {
+        let request = setup_request(());
+
+        let response = handle_request(request.clone(), unwrap_or_zero).await;
+        assert_eq!(response, Response::result(json!(0), request.id));
+    }
+
+    #[tokio::test]
+    async fn request_omitted_params_required() {
+        let request = Request {
+            params: None,
+            ..setup_request(())
+        };
+
+        let response = handle_request(request, increment).await;
+        assert_eq!(response.error.unwrap().code, ErrorCode::InvalidParams);
+    }
+
+    #[tokio::test]
+    async fn request_omitted_params_default() {
+        let request = Request {
+            params: None,
+            ..setup_request(())
+        };
+
+        let omitted = omitted!(DefaultedParams);
+        let response = handle_request_or(request.clone(), value, omitted).await;
+        assert_eq!(response, Response::result(json!(0), request.id.clone()));
+
+        // Without `Default`, what `{}` deserializes to
+        let omitted = omitted!(OptionalParams);
+        let response = handle_request_or(request.clone(), unwrap_or_zero, omitted).await;
+        assert_eq!(response, Response::result(json!(0), request.id.clone()));
+
+        let response = handle_request(request, value).await;
+        assert_eq!(response.error.unwrap().code, ErrorCode::InvalidParams);
+    }
+
+    #[tokio::test]
+    async fn request_custom_error() {
+        let request = setup_request(());
+
+        let response = handle_request(request, reject).await;
+        let json = serde_json::to_value(&response).unwrap();
+        assert_eq!(json["error"]["code"], 1000);
+        assert_eq!(json["error"]["message"], "rejected");
+        assert_eq!(json["error"]["data"], 42);
+
+        let response: Response = serde_json::from_value(json).unwrap();
+        assert_eq!(response.error.unwrap().code, ErrorCode::Custom(1000));
+    }
+
     #[tokio::test]
     #[should_panic(expected = "success")]
     async fn notification_valid() {
         let notification = setup_notification();
-        handle_notification(notification, panic).await;
+        let _ = handle_notification(notification, panic).await;
     }
 
     #[tokio::test]
-    #[should_panic]
     async fn notification_invalid_params() {
         let notification = Notification {
-            params: json!(0),
+            params: Some(json!(0)),
             ..setup_notification()
         };
-        handle_notification(notification, panic).await;
+
+        let error = handle_notification(notification, panic).await.unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidParams);
+        assert_eq!(
+            error.data,
+            Some(json!("invalid type: integer `0`, expected unit"))
+        );
     }
 }
@@ -1,5 +1,5 @@
 use serde::{Deserialize, Serialize};
-use serde_repr::*;
+use std::time::Duration;
 
 pub const PROTOCOL_VERSION: &str = "2.0";
 
@@ -10,17 +10,54 @@ pub enum Id {
     String(String),
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq, Deserialize_repr, Serialize_repr)]
-#[repr(i32)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(from = "i32", into = "i32")]
 pub enum ErrorCode {
-    ParseError = -32700,
-    InvalidRequest = -32600,
-    MethodNotFound = -32601,
-    InvalidParams = -32602,
-    InternalError = -32603,
-    ServerNotInitialized = -32002,
-    UnknownErrorCode = -32001,
-    RequestCancelled = -32800,
+    ParseError,
+    InvalidRequest,
+    MethodNotFound,
+    InvalidParams,
+    InternalError,
+    RequestTimeout,
+    ServerNotInitialized,
+    UnknownErrorCode,
+    RequestCancelled,
+    /// A code defined by the application
+    Custom(i32),
+}
+
+impl From<ErrorCode> for i32 {
+    fn from(code: ErrorCode) -> Self {
+        match code {
+            ErrorCode::ParseError => -32700,
+            ErrorCode::InvalidRequest => -32600,
+            ErrorCode::MethodNotFound => -32601,
+            ErrorCode::InvalidParams => -32602,
+            ErrorCode::InternalError => -32603,
+            ErrorCode::RequestTimeout => -32003,
+            ErrorCode::ServerNotInitialized => -32002,
+            ErrorCode::UnknownErrorCode => -32001,
+            ErrorCode::RequestCancelled => -32800,
+            ErrorCode::Custom(code) => code,
+        }
+    }
+}
+
+impl From<i32> for ErrorCode {
+    fn from(code: i32) -> Self {
+        match code {
+            -32700 => Self::ParseError,
+            -32600 => Self::InvalidRequest,
+            -32601 => Self::MethodNotFound,
+            -32602 => Self::InvalidParams,
+            -32603 => Self::InternalError,
+            -32003 => Self::RequestTimeout,
+            -32002 => Self::ServerNotInitialized,
+            -32001 => Self::UnknownErrorCode,
+            -32800 => Self::RequestCancelled,
+            code => Self::Custom(code),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
@@ -33,6 +70,21 @@ pub struct Error {
 }
 
 impl Error {
+    pub fn new(code: ErrorCode, message: String) -> Self {
+        Self {
+            code,
+            message,
+            data: None,
+        }
+    }
+
+    pub fn with_data(self, data: serde_json::Value) -> Self {
+        Self {
+            data: Some(data),
+            ..self
+        }
+    }
+
     pub fn parse_error() -> Self {
         Self {
             code: ErrorCode::ParseError,
@@ -41,6 +93,14 @@ impl Error {
         }
     }
 
+    pub fn invalid_request_error(message: String) -> Self {
+        Self {
+            code: ErrorCode::InvalidRequest,
+            message,
+            data: None,
+        }
+    }
+
     pub fn method_not_found_error() -> Self {
         Self {
             code: ErrorCode::MethodNotFound,
@@ -64,13 +124,52 @@ impl Error {
             data: None,
         }
     }
+
+    pub fn server_not_initialized_error() -> Self {
+        Self {
+            code: ErrorCode::ServerNotInitialized,
+            message: "Server has not been initialized".to_owned(),
+            data: None,
+        }
+    }
+
+    pub fn request_cancelled_error() -> Self {
+        Self {
+            code: ErrorCode::RequestCancelled,
+            message: "Request cancelled".to_owned(),
+            data: None,
+        }
+    }
+
+    pub fn request_dropped_error(method: &str) -> Self {
+        Self {
+            code: ErrorCode::RequestCancelled,
+            message: format!("Request \"{}\" was dropped", method),
+            data: None,
+        }
+    }
+
+    pub fn request_timeout_error(method: &str, timeout: Duration) -> Self {
+        Self {
+            code: ErrorCode::RequestTimeout,
+            message: format!(
+                "Request \"{}\" timed out after {} ms",
+                method,
+                timeout.as_millis()
+            ),
+            data: None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct Request {
     pub jsonrpc: String,
     pub method: String,
-    pub params: serde_json::Value,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub params: Option<serde_json::Value>,
+
     pub id: Id,
 }
 
@@ -79,7 +178,7 @@ impl Request {
         Self {
             jsonrpc: PROTOCOL_VERSION.to_owned(),
             method,
-            params,
+            params: omit_null(params),
             id,
         }
     }
@@ -122,7 +221,9 @@ impl Response {
 pub struct Notification {
     pub jsonrpc: String,
     pub method: String,
-    pub params: serde_json::Value,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub params: Option<serde_json::Value>,
 }
 
 impl Notification {
@@ -130,11 +231,20 @@ impl Notification {
         Self {
             jsonrpc: PROTOCOL_VERSION.to_owned(),
             method,
-            params,
+            params: omit_null(params),
         }
     }
 }
 
+fn omit_null(params: serde_json::Value) -> Option<serde_json::Value> {
+    Some(params).filter(|params| !params.is_null())
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct CancelParams {
+    pub id: Id,
+}
+
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 #[serde(untagged)]
 pub enum Message {
@@ -24,6 +24,7 @@ enum MethodKind {
 struct MethodMeta {
     pub name: String,
     pub kind: MethodKind,
+    pub timeout: Option<u64>,
 }
 
 impl MethodMeta {
@@ -35,19 +36,36 @@ impl MethodMeta {
 
         let nested = unwrap!(meta, Meta::List(x) => x.nested);
         let name = unwrap!(&nested[0], NestedMeta::Lit(Lit::Str(x)) => x.value());
-        let kind = {
-            let lit = unwrap!(&nested[1], NestedMeta::Meta(Meta::NameValue(x)) => &x.lit);
-            let kind = unwrap!(lit, Lit::Str(x) => x.value());
-            match kind.as_str() {
-                "request" => MethodKind::Request,
-                "notification" => MethodKind::Notification,
-                _ => panic!(
-                    "Invalid method kind. Valid options are \"request\" and \"notification\""
-                ),
+        let mut kind = None;
+        let mut timeout = None;
+        for arg in nested.iter().skip(1) {
+            let arg = unwrap!(arg, NestedMeta::Meta(Meta::NameValue(x)) => x);
+            let key = arg.path.get_ident().unwrap().to_string();
+            match key.as_str() {
+                "kind" => {
+                    let value = unwrap!(&arg.lit, Lit::Str(x) => x.value());
+                    kind = match value.as_str() {
+                        "request" => Some(MethodKind::Request),
+                        "notification" => Some(MethodKind::Notification),
+                        _ => panic!(
+                            "Invalid method kind. Valid options are \"request\" and \"notification\""
+                        ),
+                    };
+                }
+                "timeout" => {
+                    let value = unwrap!(&arg.lit, Lit::Int(x) => x.base10_parse::<u64>().unwrap());
+                    timeout = Some(value);
+                }
+                _ => panic!("Unknown argument \"{}\"", key),
             }
-        };
+        }
+        let kind = kind.expect("Expected method kind");
 
-        Self { name, kind }
+        Self {
+            name,
+            kind,
+            timeout,
+        }
     }
 }
 
@@ -79,10 +97,16 @@ pub fn jsonrpc_server(_attr: TokenStream, item: TokenStream) -> TokenStream {
                 }
             }
 
-            async fn handle_notification(&self, notification: jsonrpc::Notification) {
+            async fn handle_notification(
+                &self,
+                notification: jsonrpc::Notification,
+            ) -> std::result::Result<(), jsonrpc::Error> {
                 match notification.method.as_str() {
                     #(#notifications),*,
-                    _ => log::warn!("{}: {}", "Method not found", notification.method),
+                    _ => {
+                        log::warn!("{}: {}", "Method not found", notification.method);
+                        Ok(())
+                    }
                 }
             }
         }
@@ -114,6 +138,15 @@ pub fn jsonrpc_client(attr: TokenStream, item: TokenStream) -> TokenStream {
                     client: jsonrpc::Client::new(output),
                 }
             }
+
+            pub fn with_timeout(
+                output: futures::channel::mpsc::Sender<String>,
+                timeout: Option<std::time::Duration>,
+            ) -> Self {
+                Self {
+                    client: jsonrpc::Client::with_timeout(output, timeout),
+                }
+            }
         }
 
         #[async_trait::async_trait]
@@ -147,6 +180,11 @@ fn generate_server_skeletons(items: &Vec<ImplItem>) -> (Vec<TokenStream2>, Vec<T
         let param_ty = unwrap!(&method.sig.inputs[1], FnArg::Typed(x) => &x.ty);
         let meta = MethodMeta::parse(method.attrs.first().unwrap());
         let name = &meta.name.as_str();
+        // See `jsonrpc::OmittedParams`
+        let omitted = quote!(|| {
+            use jsonrpc::{DefaultParams, DeserializedParams};
+            (&jsonrpc::OmittedParams::<#param_ty>::new()).value()
+        });
 
         match meta.kind {
             MethodKind::Request => {
@@ -156,7 +194,7 @@ fn generate_server_skeletons(items: &Vec<ImplItem>) -> (Vec<TokenStream2>, Vec<T
                            self.#ident(param).await
                         };
 
-                        jsonrpc::handle_request(request, handler).await
+                        jsonrpc::handle_request_or(request, handler, #omitted).await
                     }
                 ));
             }
@@ -167,7 +205,7 @@ fn generate_server_skeletons(items: &Vec<ImplItem>) -> (Vec<TokenStream2>, Vec<T
                            self.#ident(param).await;
                         };
 
-                        jsonrpc::handle_notification(notification, handler).await;
+                        jsonrpc::handle_notification_or(notification, handler, #omitted).await
                     }
                 ));
             }
@@ -187,14 +225,28 @@ fn generate_client_stubs(items: &Vec<TraitItem>) -> Vec<TokenStream2> {
         let meta = MethodMeta::parse(attrs.first().unwrap());
         let name = &meta.name;
 
-        let stub = match meta.kind {
-            MethodKind::Request => quote!(
+        let stub = match (meta.kind, meta.timeout) {
+            (MethodKind::Request, None) => quote!(
                 #sig {
                     let result = self.client.send_request(#name.to_owned(), #param).await?;
-                    serde_json::from_value(result).map_err(|_| jsonrpc::Error::deserialize_error())
+                    serde_json::from_value(result).map_err(|why| {
+                        jsonrpc::Error::deserialize_error().with_data(serde_json::json!(why.to_string()))
+                    })
+                }
+            ),
+            (MethodKind::Request, Some(timeout)) => quote!(
+                #sig {
+                    let timeout = Some(std::time::Duration::from_millis(#timeout));
+                    let result = self
+                        .client
+                        .send_request_with_timeout(#name.to_owned(), #param, timeout)
+                        .await?;
+                    serde_json::from_value(result).map_err(|why| {
+                        jsonrpc::Error::deserialize_error().with_data(serde_json::json!(why.to_string()))
+                    })
                 }
             ),
-            MethodKind::Notification => quote!(
+            (MethodKind::Notification, _) => quote!(
                 #sig {
                     self.client.send_notification(#name.to_owned(), #param).await
                 }
@@ -0,0 +1,611 @@
+use super::{
+    client_config, ConnectionSettings, IrisBackend, IrisConnector, IrisError, ResultSet, Statement,
+};
+use rustls::ClientConfig;
+use serde::{
+    de::{self, DeserializeOwned, MapAccess, Visitor},
+    Deserialize, Deserializer,
+};
+use serde_json::{json, Value};
+use std::{fmt, sync::Arc, time::Duration};
+use ureq::Agent;
+use url::Url;
+
+/// How long a single request to the web server may take, unless a statement allows more.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Connects through the Atelier REST API that the IRIS web server exposes on `port`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AtelierConnector;
+
+impl IrisConnector for AtelierConnector {
+    fn connect(&self, settings: &ConnectionSettings) -> Result<Box<dyn IrisBackend>, IrisError> {
+        let scheme = if settings.https { "https" } else { "http" };
+        let url = format!("{}://{}:{}/", scheme, settings.host, settings.port);
+        let mut base = Url::parse(&url).map_err(IrisError::network)?;
+        base.path_segments_mut()
+            .map_err(|_| IrisError::network("invalid web server URL"))?
+            .pop_if_empty()
+            .extend(
+                settings
+                    .path_prefix
+                    .split('/')
+                    .filter(|segment| !segment.is_empty()),
+            )
+            .extend(&["api", "atelier", ""]);
+        let mut agent = Agent::new();
+        agent.auth(&settings.username, &settings.password);
+        let tls_config = match &settings.tls {
+            Some(tls) if settings.https => Some(client_config(tls)?),
+            _ => None,
+        };
+
+        let mut backend = AtelierBackend {
+            agent,
+            tls_config,
+            base,
+            ns: settings.ns.to_owned(),
+            version: String::new(),
+        };
+        let reply = backend.call::<ServerInfo>("GET", &[], None, TIMEOUT)?;
+        backend.version = match reply.content {
+            Some(info) => info.version,
+            None => {
+                return Err(IrisError::Server {
+                    message: format!("Atelier API did not report a version: {}", reply.summary),
+                })
+            }
+        };
+        Ok(Box::new(backend))
+    }
+}
+
+struct AtelierBackend {
+    agent: Agent,
+    tls_config: Option<Arc<ClientConfig>>,
+    base: Url,
+    ns: String,
+    version: String,
+}
+
+/// The `content` of a response together with the error summary in its `status`.
+struct Reply<T> {
+    status: u16,
+    content: Option<T>,
+    summary: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Envelope {
+    status: Status,
+}
+
+/// Parsed straight from the response text: going through a `Value` would sort the
+/// columns of query rows by name.
+#[derive(Debug, Deserialize)]
+struct Content<T> {
+    result: ContentResult<T>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ContentResult<T> {
+    content: Option<T>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Status {
+    errors: Vec<Value>,
+    summary: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ServerInfo {
+    version: String,
+}
+
+impl AtelierBackend {
+    /// Sends a request to `segments` below `/api/atelier/`. Transport failures, rejected
+    /// credentials, missing privileges and unknown namespaces are errors; any other error
+    /// that IRIS reports ends up in the summary of the reply.
+    fn call<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        segments: &[&str],
+        body: Option<Value>,
+        timeout: Duration,
+    ) -> Result<Reply<T>, IrisError> {
+        let mut url = self.base.clone();
+        if !segments.is_empty() {
+            url.path_segments_mut()
+                .map_err(|_| IrisError::network("invalid base URL"))?
+                .pop_if_empty()
+                .extend(segments);
+        }
+
+        let mut request = self.agent.request(method, url.as_str());
+        request.timeout(timeout);
+        if let Some(tls_config) = &self.tls_config {
+            request.set_tls_config(Arc::clone(tls_config));
+        }
+        let response = match body {
+            Some(body) => request.send_json(body),
+            None => request.call(),
+        };
+        if let Some(why) = response.synthetic_error() {
+            return Err(IrisError::network(why));
+        }
+
+        let status = response.status();
+        let status_line = format!("HTTP {} {}", status, response.status_text());
+        let text = response.into_string().map_err(IrisError::network)?;
+        // IRIS answers 401 and 403 without an envelope
+        let envelope: Envelope = serde_json::from_str(&text).unwrap_or_default();
+        let mut summary = envelope.status.summary;
+        if summary.is_empty() && !envelope.status.errors.is_empty() {
+            summary = Value::Array(envelope.status.errors).to_string();
+        }
+        if summary.is_empty() && !(200..300).contains(&status) {
+            summary = status_line;
+        }
+
+        match status {
+            401 => return Err(IrisError::AuthFailed { message: summary }),
+            403 => return Err(IrisError::PermissionDenied { message: summary }),
+            404 if summary.to_lowercase().contains("namespace") => {
+                return Err(IrisError::NamespaceNotFound {
+                    namespace: self.ns.to_owned(),
+                })
+            }
+            _ => {}
+        }
+
+        let content = if summary.is_empty() {
+            let content: Content<T> =
+                serde_json::from_str(&text).map_err(|why| IrisError::Server {
+                    message: format!("Unexpected response from the Atelier API: {}", why),
+                })?;
+            content.result.content
+        } else {
+            None
+        };
+        Ok(Reply {
+            status,
+            content,
+            summary,
+        })
+    }
+
+    fn v1(&self, segments: &[&str]) -> Vec<String> {
+        let mut path = vec![String::from("v1"), self.ns.to_owned()];
+        path.extend(segments.iter().map(|segment| String::from(*segment)));
+        path
+    }
+}
+
+impl IrisBackend for AtelierBackend {
+    fn server_version(&mut self) -> Result<String, IrisError> {
+        Ok(self.version.clone())
+    }
+
+    fn classmethod(&mut self, class: &str, method: &str) -> Result<String, IrisError> {
+        Err(IrisError::Unsupported {
+            operation: format!("Calling {}:{}", class, method),
+        })
+    }
+
+    fn production_state(&mut self) -> Result<Option<(String, i32)>, IrisError> {
+        Ok(None)
+    }
+
+    fn query<'a>(
+        &'a mut self,
+        statement: &Statement,
+    ) -> Result<Box<dyn ResultSet + 'a>, IrisError> {
+        let path = self.v1(&["action", "query"]);
+        let path: Vec<&str> = path.iter().map(String::as_str).collect();
+        let body = json!({ "query": statement.sql(), "parameters": statement.params() });
+        let timeout = TIMEOUT + statement.timeout().unwrap_or_default();
+        let reply = self.call::<Vec<Row>>("POST", &path, Some(body), timeout)?;
+        if !reply.summary.is_empty() {
+            return Err(IrisError::sql(reply.summary));
+        }
+        Ok(Box::new(AtelierResultSet {
+            rows: reply.content.unwrap_or_default(),
+            current: None,
+        }))
+    }
+
+    fn document(&mut self, name: &str) -> Result<Option<Vec<String>>, IrisError> {
+        let path = self.v1(&["doc", name]);
+        let path: Vec<&str> = path.iter().map(String::as_str).collect();
+        let reply = self.call::<Vec<String>>("GET", &path, None, TIMEOUT)?;
+        match reply.status {
+            404 => Ok(None),
+            _ if reply.summary.is_empty() => Ok(reply.content),
+            _ => Err(IrisError::Server {
+                message: reply.summary,
+            }),
+        }
+    }
+
+    fn compile(&mut self, names: &[String]) -> Result<(), IrisError> {
+        let path = self.v1(&["action", "compile"]);
+        let path: Vec<&str> = path.iter().map(String::as_str).collect();
+        let reply = self.call::<Value>("POST", &path, Some(json!(names)), TIMEOUT)?;
+        if reply.summary.is_empty() {
+            Ok(())
+        } else {
+            Err(IrisError::Server {
+                message: reply.summary,
+            })
+        }
+    }
+}
+
+/// A query result row. Atelier sends rows as objects, so the column order is the
+/// order of the keys in the response.
+#[derive(Debug, Default)]
+struct Row(Vec<Option<String>>);
+
+impl<'de> Deserialize<'de> for Row {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct RowVisitor;
+
+        impl<'de> Visitor<'de> for RowVisitor {
+            type Value = Row;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a row object")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Row, A::Error> {
+                let mut columns = Vec::new();
+                while let Some((_, value)) = map.next_entry::<de::IgnoredAny, Value>()? {
+                    columns.push(match value {
+                        Value::Null => None,
+                        Value::String(value) => Some(value),
+                        value => Some(value.to_string()),
+                    });
+                }
+                Ok(Row(columns))
+            }
+        }
+
+        deserializer.deserialize_map(RowVisitor)
+    }
+}
+
+struct AtelierResultSet {
+    rows: Vec<Row>,
+    current: Option<usize>,
+}
+
+impl ResultSet for AtelierResultSet {
+    fn next(&mut self) -> bool {
+        let next = self.current.map_or(0, |current| current + 1);
+        self.current = Some(next);
+        next < self.rows.len()
+    }
+
+    fn get(&self, index: usize) -> Option<String> {
+        self.rows.get(self.current?)?.0.get(index).cloned()?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::ConnBackend;
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::{Ipv4Addr, TcpListener},
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    #[derive(Debug, Clone)]
+    struct Recorded {
+        method: String,
+        path: String,
+        authorization: Option<String>,
+        body: String,
+    }
+
+    /// Serves canned responses on a local port, one request per connection.
+    /// `respond` gets the method and path and returns the status and the JSON body,
+    /// which is sent as is so that tests control the order of object keys.
+    fn mock_server<F>(respond: F) -> (u16, Arc<Mutex<Vec<Recorded>>>)
+    where
+        F: Fn(&str, &str) -> (u16, String) + Send + 'static,
+    {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&requests);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => break,
+                };
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let mut parts = line.split_whitespace();
+                let method = parts.next().unwrap_or_default().to_owned();
+                let path = parts.next().unwrap_or_default().to_owned();
+
+                let mut length = 0;
+                let mut authorization = None;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    let header = header.trim();
+                    if header.is_empty() {
+                        break;
+                    }
+                    let (name, value) = header.split_at(header.find(':').unwrap());
+                    let value = value[1..].trim();
+                    match name.to_lowercase().as_str() {
+                        "content-length" => length = value.parse().unwrap(),
+                        "authorization" => authorization = Some(value.to_owned()),
+                        _ => {}
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+
+                let (status, response) = respond(&method, &path);
+                recorded.lock().unwrap().push(Recorded {
+                    method,
+                    path,
+                    authorization,
+                    body: String::from_utf8(body).unwrap(),
+                });
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    response.len(),
+                    response
+                );
+            }
+        });
+        (port, requests)
+    }
+
+    fn envelope(content: &str) -> String {
+        format!(
+            r#"{{"status":{{"errors":[],"summary":""}},"console":[],"result":{{"content":{}}}}}"#,
+            content
+        )
+    }
+
+    fn server_info() -> String {
+        envelope(r#"{"version":"IRIS for UNIX 2020.1","api":6}"#)
+    }
+
+    fn settings(port: u16) -> ConnectionSettings {
+        ConnectionSettings {
+            backend: ConnBackend::Atelier,
+            host: String::from("127.0.0.1"),
+            port,
+            ns: String::from("%SYS"),
+            username: String::from("_SYSTEM"),
+            password: String::from("SYS"),
+            ..ConnectionSettings::default()
+        }
+    }
+
+    #[test]
+    fn connect_reads_server_version() {
+        let (port, requests) = mock_server(|_, _| (200, server_info()));
+        let mut backend = AtelierConnector.connect(&settings(port)).unwrap();
+
+        assert_eq!(backend.server_version().unwrap(), "IRIS for UNIX 2020.1");
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests[0].method, "GET");
+        assert_eq!(requests[0].path, "/api/atelier/");
+        assert_eq!(
+            requests[0].authorization.as_deref(),
+            Some("Basic X1NZU1RFTTpTWVM=")
+        );
+    }
+
+    #[test]
+    fn path_prefix_goes_before_api() {
+        let (port, requests) = mock_server(|_, _| (200, server_info()));
+        let settings = ConnectionSettings {
+            path_prefix: String::from("/iris/"),
+            ..settings(port)
+        };
+        AtelierConnector.connect(&settings).unwrap();
+
+        assert_eq!(requests.lock().unwrap()[0].path, "/iris/api/atelier/");
+    }
+
+    #[test]
+    fn connect_fails_without_authorization() {
+        let (port, _) = mock_server(|_, _| (401, String::new()));
+        assert!(matches!(
+            AtelierConnector.connect(&settings(port)).err(),
+            Some(IrisError::AuthFailed { .. })
+        ));
+    }
+
+    #[test]
+    fn forbidden_query_is_permission_denied() {
+        let (port, _) = mock_server(|_, path| {
+            if path.ends_with("/action/query") {
+                (403, String::new())
+            } else {
+                (200, server_info())
+            }
+        });
+        let mut backend = AtelierConnector.connect(&settings(port)).unwrap();
+        assert!(matches!(
+            backend.query(&Statement::new("SELECT 1")).err(),
+            Some(IrisError::PermissionDenied { .. })
+        ));
+    }
+
+    #[test]
+    fn query_binds_parameters_and_keeps_column_order() {
+        let (port, requests) = mock_server(|_, path| {
+            if path.ends_with("/action/query") {
+                let rows = r#"[
+                    {"Name":"Demo.Production","Super":"Ens.Production","Count":2},
+                    {"Name":"Other.Production","Super":null,"Count":0}
+                ]"#;
+                (200, envelope(rows))
+            } else {
+                (200, server_info())
+            }
+        });
+        let mut backend = AtelierConnector.connect(&settings(port)).unwrap();
+        let statement =
+            Statement::new("SELECT Name, Super, Count FROM Productions WHERE Super = ?")
+                .bind("Ens.Production");
+        let mut rows = Vec::new();
+        {
+            let mut rs = backend.query(&statement).unwrap();
+            while rs.next() {
+                rows.push((rs.get(0), rs.get(1), rs.get(2), rs.get(3)));
+            }
+        }
+
+        assert_eq!(
+            rows,
+            vec![
+                (
+                    Some(String::from("Demo.Production")),
+                    Some(String::from("Ens.Production")),
+                    Some(String::from("2")),
+                    None
+                ),
+                (
+                    Some(String::from("Other.Production")),
+                    None,
+                    Some(String::from("0")),
+                    None
+                ),
+            ]
+        );
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests[1].method, "POST");
+        assert_eq!(requests[1].path, "/api/atelier/v1/%25SYS/action/query");
+        let body: Value = serde_json::from_str(&requests[1].body).unwrap();
+        assert_eq!(body["query"], statement.sql());
+        assert_eq!(body["parameters"], json!(["Ens.Production"]));
+    }
+
+    #[test]
+    fn failed_query_is_sql_error() {
+        let (port, _) = mock_server(|_, path| {
+            if path.ends_with("/action/query") {
+                let summary =
+                    "ERROR #5540: SQLCODE: -30 Message: Table 'SQLUSER.MISSING' not found";
+                let status = json!({
+                    "status": { "errors": [{ "error": summary }], "summary": summary },
+                    "result": {}
+                });
+                (400, status.to_string())
+            } else {
+                (200, server_info())
+            }
+        });
+        let mut backend = AtelierConnector.connect(&settings(port)).unwrap();
+        assert!(matches!(
+            backend
+                .query(&Statement::new("SELECT * FROM Missing"))
+                .err(),
+            Some(IrisError::Sql {
+                sqlcode: Some(-30),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn document_lines() {
+        let (port, requests) = mock_server(|_, path| {
+            if path.ends_with("/doc/Missing.cls") {
+                let status = json!({
+                    "status": { "errors": [], "summary": "ERROR #16005: Document 'Missing.cls' does NOT exist" },
+                    "result": {}
+                });
+                (404, status.to_string())
+            } else if path.contains("/doc/") {
+                let content = r#"["Class Demo.Production Extends Ens.Production","{","}"]"#;
+                (200, envelope(content))
+            } else {
+                (200, server_info())
+            }
+        });
+        let mut backend = AtelierConnector.connect(&settings(port)).unwrap();
+
+        assert_eq!(
+            backend.document("Demo.Production.cls"),
+            Ok(Some(vec![
+                String::from("Class Demo.Production Extends Ens.Production"),
+                String::from("{"),
+                String::from("}"),
+            ]))
+        );
+        assert_eq!(backend.document("Missing.cls"), Ok(None));
+        let requests = requests.lock().unwrap();
+        assert_eq!(
+            requests[1].path,
+            "/api/atelier/v1/%25SYS/doc/Demo.Production.cls"
+        );
+    }
+
+    #[test]
+    fn compile_reports_errors() {
+        let (port, requests) = mock_server(|_, path| {
+            if path.ends_with("/action/compile") {
+                let status = json!({
+                    "status": { "errors": [], "summary": "ERROR #5373: Class 'Missing' does not exist" },
+                    "result": {}
+                });
+                (200, status.to_string())
+            } else {
+                (200, server_info())
+            }
+        });
+        let mut backend = AtelierConnector.connect(&settings(port)).unwrap();
+
+        assert_eq!(
+            backend.compile(&[String::from("Missing.cls")]),
+            Err(IrisError::Server {
+                message: String::from("ERROR #5373: Class 'Missing' does not exist")
+            })
+        );
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests[1].path, "/api/atelier/v1/%25SYS/action/compile");
+        assert_eq!(requests[1].body, r#"["Missing.cls"]"#);
+    }
+
+    #[test]
+    fn unreachable_web_server_is_network_error() {
+        let port = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut backend = AtelierBackend {
+            agent: Agent::new(),
+            tls_config: None,
+            base: Url::parse(&format!("http://127.0.0.1:{}/api/atelier/", port)).unwrap(),
+            ns: String::from("USER"),
+            version: String::new(),
+        };
+        assert!(backend
+            .query(&Statement::new("SELECT 1"))
+            .err()
+            .is_some_and(|error| error.is_lost_connection()));
+    }
+}
@@ -0,0 +1,152 @@
+use serde::{Deserialize, Serialize};
+use std::{error, fmt};
+
+/// Why an operation on IRIS failed.
+///
+/// Serialized with a `kind` tag, this is the `data` of the JSON-RPC error that the
+/// client receives, and [`IrisError::code`] is its code.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", tag = "kind")]
+pub enum IrisError {
+    /// The connection settings are incomplete or the connection is inactive
+    NotConfigured,
+    AuthFailed {
+        message: String,
+    },
+    /// IRIS cannot be reached or the connection broke
+    Network {
+        message: String,
+    },
+    NamespaceNotFound {
+        namespace: String,
+    },
+    PermissionDenied {
+        message: String,
+    },
+    Sql {
+        sqlcode: Option<i32>,
+        message: String,
+    },
+    /// The backend has no way to perform the operation
+    Unsupported {
+        operation: String,
+    },
+    /// Any other error that IRIS reports, e.g. a failed compilation
+    Server {
+        message: String,
+    },
+    /// The `SETTINGS` of the host class and its adapter do not declare `setting`
+    UnknownSetting {
+        item: String,
+        setting: String,
+        /// The settings the item does have
+        known: Vec<String>,
+    },
+}
+
+impl IrisError {
+    pub fn code(&self) -> i32 {
+        match self {
+            Self::NotConfigured => 1000,
+            Self::AuthFailed { .. } => 1001,
+            Self::Network { .. } => 1002,
+            Self::NamespaceNotFound { .. } => 1003,
+            Self::PermissionDenied { .. } => 1004,
+            Self::Sql { .. } => 1005,
+            Self::Unsupported { .. } => 1006,
+            Self::Server { .. } => 1007,
+            Self::UnknownSetting { .. } => 1008,
+        }
+    }
+
+    pub fn network(message: impl fmt::Display) -> Self {
+        Self::Network {
+            message: message.to_string(),
+        }
+    }
+
+    /// Parses the `SQLCODE: -30` or `SQLCODE: <-30>` that IRIS puts into the text
+    /// of SQL errors.
+    pub fn sql(message: impl Into<String>) -> Self {
+        let message = message.into();
+        let sqlcode = message.find("SQLCODE:").and_then(|start| {
+            let rest = message[start + "SQLCODE:".len()..].trim_start();
+            let rest = rest.strip_prefix('<').unwrap_or(rest);
+            let end = rest
+                .char_indices()
+                .find(|&(i, c)| !(c.is_ascii_digit() || (i == 0 && c == '-')))
+                .map_or(rest.len(), |(i, _)| i);
+            rest[..end].parse().ok()
+        });
+        Self::Sql { sqlcode, message }
+    }
+
+    /// Whether the connection is gone and has to be opened again.
+    pub fn is_lost_connection(&self) -> bool {
+        matches!(self, Self::Network { .. })
+    }
+}
+
+impl fmt::Display for IrisError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NotConfigured => write!(f, "No active connection is configured"),
+            Self::AuthFailed { message } => write!(f, "Authentication failed: {}", message),
+            Self::Network { message } => write!(f, "Cannot reach IRIS: {}", message),
+            Self::NamespaceNotFound { namespace } => {
+                write!(f, "Namespace {} does not exist", namespace)
+            }
+            Self::PermissionDenied { message } => write!(f, "Permission denied: {}", message),
+            Self::Sql { message, .. } => write!(f, "SQL error: {}", message),
+            Self::Unsupported { operation } => {
+                write!(f, "{} is not supported by this connection", operation)
+            }
+            Self::Server { message } => write!(f, "{}", message),
+            Self::UnknownSetting { item, setting, .. } => {
+                write!(f, "{} has no setting {}", item, setting)
+            }
+        }
+    }
+}
+
+impl error::Error for IrisError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn serialize_with_kind() {
+        let error = IrisError::AuthFailed {
+            message: String::from("Access Denied"),
+        };
+        assert_eq!(
+            serde_json::to_value(&error).unwrap(),
+            json!({ "kind": "authFailed", "message": "Access Denied" })
+        );
+        assert_eq!(
+            serde_json::to_value(&IrisError::NotConfigured).unwrap(),
+            json!({ "kind": "notConfigured" })
+        );
+    }
+
+    #[test]
+    fn sqlcode_from_message() {
+        let error = IrisError::sql("ERROR #5540: SQLCODE: -30 Message: Table 'X' not found");
+        assert_eq!(
+            error,
+            IrisError::Sql {
+                sqlcode: Some(-30),
+                message: String::from("ERROR #5540: SQLCODE: -30 Message: Table 'X' not found"),
+            }
+        );
+        assert_eq!(
+            IrisError::sql("syntax error"),
+            IrisError::Sql {
+                sqlcode: None,
+                message: String::from("syntax error"),
+            }
+        );
+    }
+}
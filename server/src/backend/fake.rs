@@ -0,0 +1,232 @@
+use super::{ConnectionSettings, IrisBackend, IrisConnector, IrisError, ResultSet, Statement};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+/// Canned answers served by [`FakeIris`].
+#[derive(Debug, PartialEq, Eq, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Fixtures {
+    pub version: String,
+
+    /// Name and state of the current production
+    pub production_state: (String, i32),
+
+    /// Answer like a backend that cannot tell the production state, such as the Atelier API
+    pub production_state_unknown: bool,
+
+    /// Results of class methods, keyed by `Class:Method`
+    pub classmethods: HashMap<String, String>,
+
+    pub queries: Vec<QueryFixture>,
+
+    /// Sources of documents, keyed by name
+    pub documents: HashMap<String, Vec<String>>,
+}
+
+/// Rows returned for every query that contains `matches` (ignoring case)
+/// and, if `params` is set, has exactly these parameters bound.
+/// If `error` is set, the query fails with it instead.
+#[derive(Debug, PartialEq, Eq, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct QueryFixture {
+    pub matches: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub params: Option<Vec<String>>,
+    pub rows: Vec<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<IrisError>,
+}
+
+#[derive(Debug, Default)]
+struct FakeState {
+    fixtures: Fixtures,
+    connect_error: Option<IrisError>,
+    broken: bool,
+    latency: Duration,
+    connections: Vec<ConnectionSettings>,
+    statements: Vec<Statement>,
+    compiled: Vec<String>,
+}
+
+/// An in-memory IRIS instance. Clones share their fixtures and the query log.
+#[derive(Debug, Clone, Default)]
+pub struct FakeIris {
+    state: Arc<Mutex<FakeState>>,
+}
+
+impl FakeIris {
+    pub fn new(fixtures: Fixtures) -> Self {
+        let state = FakeState {
+            fixtures,
+            ..FakeState::default()
+        };
+        Self {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    pub fn refuse_connections(&self, refuse: bool) {
+        let error = Some(IrisError::network("connection refused")).filter(|_| refuse);
+        self.reject_connections(error);
+    }
+
+    /// Makes every new connection fail with `error`, or succeed again if it is `None`.
+    pub fn reject_connections(&self, error: Option<IrisError>) {
+        self.state.lock().unwrap().connect_error = error;
+    }
+
+    /// Makes every query block for `latency` before it returns.
+    pub fn set_latency(&self, latency: Duration) {
+        self.state.lock().unwrap().latency = latency;
+    }
+
+    /// Makes the next call on an open connection fail like a dropped socket.
+    pub fn break_connection(&self) {
+        self.state.lock().unwrap().broken = true;
+    }
+
+    /// Changes the fixtures, e.g. the state of the production while a test runs.
+    pub fn update_fixtures(&self, f: impl FnOnce(&mut Fixtures)) {
+        f(&mut self.state.lock().unwrap().fixtures);
+    }
+
+    /// The number of connections opened so far.
+    pub fn connections(&self) -> usize {
+        self.state.lock().unwrap().connections.len()
+    }
+
+    /// The settings of every connection opened so far, in order.
+    pub fn connection_settings(&self) -> Vec<ConnectionSettings> {
+        self.state.lock().unwrap().connections.clone()
+    }
+
+    /// Every statement that has been executed, in order.
+    pub fn statements(&self) -> Vec<Statement> {
+        self.state.lock().unwrap().statements.clone()
+    }
+
+    /// Every document that has been compiled, in order.
+    pub fn compiled(&self) -> Vec<String> {
+        self.state.lock().unwrap().compiled.clone()
+    }
+
+    fn with_state<T>(
+        &self,
+        f: impl FnOnce(&mut FakeState) -> Result<T, IrisError>,
+    ) -> Result<T, IrisError> {
+        let mut state = self.state.lock().unwrap();
+        if state.broken {
+            state.broken = false;
+            return Err(IrisError::network("connection reset by peer"));
+        }
+        f(&mut state)
+    }
+}
+
+impl IrisConnector for FakeIris {
+    fn connect(&self, settings: &ConnectionSettings) -> Result<Box<dyn IrisBackend>, IrisError> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(error) = &state.connect_error {
+            Err(error.clone())
+        } else {
+            state.connections.push(settings.clone());
+            state.broken = false;
+            Ok(Box::new(self.clone()))
+        }
+    }
+}
+
+impl IrisBackend for FakeIris {
+    fn server_version(&mut self) -> Result<String, IrisError> {
+        self.with_state(|state| Ok(state.fixtures.version.clone()))
+    }
+
+    fn classmethod(&mut self, class: &str, method: &str) -> Result<String, IrisError> {
+        let key = format!("{}:{}", class, method);
+        self.with_state(|state| {
+            let classmethods = &state.fixtures.classmethods;
+            Ok(classmethods.get(&key).cloned().unwrap_or_default())
+        })
+    }
+
+    fn production_state(&mut self) -> Result<Option<(String, i32)>, IrisError> {
+        self.with_state(|state| {
+            let fixtures = &state.fixtures;
+            Ok(Some(fixtures.production_state.clone())
+                .filter(|_| !fixtures.production_state_unknown))
+        })
+    }
+
+    fn document(&mut self, name: &str) -> Result<Option<Vec<String>>, IrisError> {
+        self.with_state(|state| Ok(state.fixtures.documents.get(name).cloned()))
+    }
+
+    fn compile(&mut self, names: &[String]) -> Result<(), IrisError> {
+        self.with_state(|state| {
+            let documents = &state.fixtures.documents;
+            match names.iter().find(|name| !documents.contains_key(*name)) {
+                Some(name) => Err(IrisError::Server {
+                    message: format!("{} does not exist", name),
+                }),
+                None => {
+                    state.compiled.extend_from_slice(names);
+                    Ok(())
+                }
+            }
+        })
+    }
+
+    fn query<'a>(
+        &'a mut self,
+        statement: &Statement,
+    ) -> Result<Box<dyn ResultSet + 'a>, IrisError> {
+        let latency = self.state.lock().unwrap().latency;
+        thread::sleep(latency);
+        let rows = self.with_state(|state| {
+            state.statements.push(statement.clone());
+            let sql = statement.sql().to_lowercase();
+            let query = state
+                .fixtures
+                .queries
+                .iter()
+                .filter(|query| sql.contains(&query.matches.to_lowercase()))
+                .find(|query| match &query.params {
+                    Some(params) => params.as_slice() == statement.params(),
+                    None => true,
+                });
+            match query {
+                Some(QueryFixture {
+                    error: Some(error), ..
+                }) => Err(error.clone()),
+                Some(query) => Ok(query.rows.clone()),
+                None => Ok(Vec::new()),
+            }
+        })?;
+        Ok(Box::new(FakeResultSet {
+            rows,
+            current: None,
+        }))
+    }
+}
+
+struct FakeResultSet {
+    rows: Vec<Vec<String>>,
+    current: Option<usize>,
+}
+
+impl ResultSet for FakeResultSet {
+    fn next(&mut self) -> bool {
+        let next = self.current.map_or(0, |current| current + 1);
+        self.current = Some(next);
+        next < self.rows.len()
+    }
+
+    fn get(&self, index: usize) -> Option<String> {
+        self.rows.get(self.current?)?.get(index).cloned()
+    }
+}
@@ -0,0 +1,255 @@
+mod atelier;
+mod error;
+mod fake;
+mod native;
+mod procedures;
+mod tls;
+
+pub use self::{
+    atelier::AtelierConnector,
+    error::IrisError,
+    fake::{FakeIris, Fixtures, QueryFixture},
+    native::NativeConnector,
+    tls::client_config,
+};
+
+use crate::protocol::ConnBackend;
+use std::{fmt, path::PathBuf, time::Duration};
+
+#[derive(PartialEq, Eq, Clone, Default)]
+pub struct ConnectionSettings {
+    pub backend: ConnBackend,
+    /// The server definition these settings come from, if any
+    pub server_name: Option<String>,
+    pub host: String,
+    pub port: u16,
+    pub super_host: String,
+    pub super_port: u16,
+    pub https: bool,
+    /// Certificates for the web server when `https` is set. The native backend refuses
+    /// connections with TLS, since its driver only speaks plain TCP.
+    pub tls: Option<TlsSettings>,
+    /// Where the web server serves IRIS, e.g. `iris` for `http://host/iris/api/atelier/`
+    pub path_prefix: String,
+    pub ns: String,
+    pub username: String,
+    pub password: String,
+}
+
+impl fmt::Debug for ConnectionSettings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ConnectionSettings")
+            .field("backend", &self.backend)
+            .field("server_name", &self.server_name)
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("super_host", &self.super_host)
+            .field("super_port", &self.super_port)
+            .field("https", &self.https)
+            .field("tls", &self.tls)
+            .field("path_prefix", &self.path_prefix)
+            .field("ns", &self.ns)
+            .field("username", &self.username)
+            .field("password", &"<redacted>")
+            .finish()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct TlsSettings {
+    /// PEM file with the certificate authorities to trust instead of the built-in roots
+    pub ca_file: Option<PathBuf>,
+    /// PEM files with a client certificate and its key, for servers that require one
+    pub cert_file: Option<PathBuf>,
+    pub key_file: Option<PathBuf>,
+    pub verify_hostname: bool,
+}
+
+impl Default for TlsSettings {
+    fn default() -> Self {
+        Self {
+            ca_file: None,
+            cert_file: None,
+            key_file: None,
+            verify_hostname: true,
+        }
+    }
+}
+
+/// The operations the language server performs on an IRIS instance.
+///
+/// A [`IrisError::Network`] error means the connection is lost, every other error leaves
+/// it usable. Implementations report failures as errors; should one panic anyway, the
+/// `ConnectionManager` drops the connection.
+pub trait IrisBackend: Send {
+    fn server_version(&mut self) -> Result<String, IrisError>;
+
+    fn classmethod(&mut self, class: &str, method: &str) -> Result<String, IrisError>;
+
+    /// The name and state of the production that is currently running or was last run,
+    /// or `None` if the backend cannot tell.
+    fn production_state(&mut self) -> Result<Option<(String, i32)>, IrisError>;
+
+    fn query<'a>(&'a mut self, statement: &Statement)
+        -> Result<Box<dyn ResultSet + 'a>, IrisError>;
+
+    /// The source of a document such as `Demo.Production.cls`, line by line,
+    /// or `None` if there is no such document. Classes are read as UDL through
+    /// `%Compiler.UDL.TextServices` in an SQL function, see the `procedures` module.
+    fn document(&mut self, name: &str) -> Result<Option<Vec<String>>, IrisError> {
+        match name.strip_suffix(".cls") {
+            Some(class) => procedures::class_source(self, class),
+            None => Err(IrisError::Unsupported {
+                operation: String::from("Reading documents other than classes"),
+            }),
+        }
+    }
+
+    fn compile(&mut self, _names: &[String]) -> Result<(), IrisError> {
+        Err(IrisError::Unsupported {
+            operation: String::from("Compiling"),
+        })
+    }
+
+    /// Starts `production`, or stops, restarts or updates the current production, through
+    /// `Ens.Director`, which waits `timeout` seconds for business hosts to finish and with
+    /// `force` stops those that do not. Runs as an SQL function, see the `procedures` module.
+    fn control_production(
+        &mut self,
+        action: ProductionAction,
+        production: &str,
+        timeout: u32,
+        force: bool,
+    ) -> Result<(), IrisError> {
+        procedures::control_production(self, action, production, timeout, force)
+    }
+
+    /// Changes item `item` of the running `production` through its `Ens.Config.Item`,
+    /// saves the production class with `SaveToClass`, so the change survives a recompile,
+    /// and applies it with `Ens.Director:UpdateProduction`, which waits `timeout` seconds
+    /// for hosts that have to stop and with `force` stops those that do not.
+    ///
+    /// This is a single call, so a backend that cannot make it changes nothing. Runs as an
+    /// SQL function, see the `procedures` module.
+    fn change_item(
+        &mut self,
+        production: &str,
+        item: &str,
+        change: &ItemChange,
+        timeout: u32,
+        force: bool,
+    ) -> Result<(), IrisError> {
+        procedures::change_item(self, production, item, change, timeout, force)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ProductionAction {
+    Start,
+    Stop,
+    Restart,
+    Update,
+}
+
+impl ProductionAction {
+    /// The `Ens.Director` method that performs the action
+    fn method(self) -> &'static str {
+        match self {
+            Self::Start => "StartProduction",
+            Self::Stop => "StopProduction",
+            Self::Restart => "RestartProduction",
+            Self::Update => "UpdateProduction",
+        }
+    }
+
+    /// Describes the action while it runs, e.g. in progress reports.
+    pub fn title(self) -> &'static str {
+        match self {
+            Self::Start => "Starting",
+            Self::Stop => "Stopping",
+            Self::Restart => "Restarting",
+            Self::Update => "Updating",
+        }
+    }
+}
+
+/// A change to an item of a production, see [`IrisBackend::change_item`].
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ItemChange {
+    Enabled(bool),
+    /// Sets a setting of the host or its adapter, or removes it with `None`, so that the
+    /// default applies again
+    Setting {
+        target: String,
+        name: String,
+        value: Option<String>,
+    },
+}
+
+/// An SQL statement with `?` placeholders and the values bound to them.
+///
+/// Values never become part of the SQL text, so they need no quoting or escaping.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Statement {
+    sql: String,
+    params: Vec<String>,
+    timeout: Option<Duration>,
+}
+
+impl Statement {
+    pub fn new(sql: impl Into<String>) -> Self {
+        Self {
+            sql: sql.into(),
+            params: Vec::new(),
+            timeout: None,
+        }
+    }
+
+    pub fn bind(mut self, value: impl Into<String>) -> Self {
+        self.params.push(value.into());
+        self
+    }
+
+    /// Lets the statement run `timeout` longer than a query usually may, for statements
+    /// that wait on the server, such as stopping a production.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn sql(&self) -> &str {
+        &self.sql
+    }
+
+    pub fn params(&self) -> &[String] {
+        &self.params
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+}
+
+pub trait ResultSet {
+    fn next(&mut self) -> bool;
+
+    fn get(&self, index: usize) -> Option<String>;
+}
+
+/// Opens new backend connections.
+pub trait IrisConnector: Send + Sync {
+    fn connect(&self, settings: &ConnectionSettings) -> Result<Box<dyn IrisBackend>, IrisError>;
+}
+
+/// Connects with the protocol chosen in the connection settings.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DefaultConnector;
+
+impl IrisConnector for DefaultConnector {
+    fn connect(&self, settings: &ConnectionSettings) -> Result<Box<dyn IrisBackend>, IrisError> {
+        match settings.backend {
+            ConnBackend::Native => NativeConnector.connect(settings),
+            ConnBackend::Atelier => AtelierConnector.connect(settings),
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use super::{ConnectionSettings, IrisBackend, IrisConnector, IrisError, ResultSet, Statement};
+use irisnative::{connection::*, Connection};
+use std::{
+    any::Any,
+    io,
+    panic::{self, AssertUnwindSafe},
+};
+
+/// Connects through the native IRIS protocol on the superserver port.
+///
+/// The driver only opens plain TCP connections by host and port, so connections
+/// with TLS enabled are refused rather than sent in the clear or relayed through a
+/// local port that other processes could connect to.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NativeConnector;
+
+impl IrisConnector for NativeConnector {
+    fn connect(&self, settings: &ConnectionSettings) -> Result<Box<dyn IrisBackend>, IrisError> {
+        if settings.tls.is_some() {
+            return Err(IrisError::Unsupported {
+                operation: String::from("TLS on the superserver"),
+            });
+        }
+        let connection = driver_call(|| {
+            Connection::connect(
+                settings.super_host.to_owned(),
+                settings.super_port,
+                settings.ns.to_owned(),
+                settings.username.to_owned(),
+                settings.password.to_owned(),
+            )
+        })?
+        .map_err(|why| connect_error(why, &settings.ns))?;
+        Ok(Box::new(NativeBackend { connection }))
+    }
+}
+
+/// The handshake reports a rejected login or namespace as a plain I/O error,
+/// so everything but the socket errors is told apart by its message.
+fn connect_error(why: io::Error, ns: &str) -> IrisError {
+    let message = why.to_string();
+    let lowercase = message.to_lowercase();
+    match why.kind() {
+        io::ErrorKind::PermissionDenied => IrisError::AuthFailed { message },
+        io::ErrorKind::Other
+            if lowercase.contains("access denied") || lowercase.contains("password") =>
+        {
+            IrisError::AuthFailed { message }
+        }
+        io::ErrorKind::Other if lowercase.contains("namespace") => IrisError::NamespaceNotFound {
+            namespace: ns.to_owned(),
+        },
+        io::ErrorKind::Other if lowercase.contains("privilege") => {
+            IrisError::PermissionDenied { message }
+        }
+        _ => IrisError::Network { message },
+    }
+}
+
+/// Runs a call into irisnative. The driver has no error results for most calls and panics
+/// instead, with the error that IRIS reported or the I/O error of the socket as its message,
+/// so this is where such a panic becomes an [`IrisError`].
+fn driver_call<T>(f: impl FnOnce() -> T) -> Result<T, IrisError> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| driver_error(&*payload))
+}
+
+/// Tells apart the errors that IRIS reports, which leave the connection usable, from
+/// failures of the connection: SQL errors come with an `SQLCODE`, other errors with an
+/// error code such as `<UNDEFINED>` or an `ERROR #` status.
+fn driver_error(payload: &(dyn Any + Send)) -> IrisError {
+    let message = match payload.downcast_ref::<String>() {
+        Some(message) => message.clone(),
+        None => payload.downcast_ref::<&str>().map_or_else(
+            || String::from("the driver failed"),
+            |message| (*message).to_owned(),
+        ),
+    };
+    let has_error_code = message
+        .split('<')
+        .skip(1)
+        .filter_map(|rest| Some(rest.split_once('>')?.0))
+        .any(|code| !code.is_empty() && code.chars().all(|c| c.is_ascii_uppercase() || c == ' '));
+    if message.contains("SQLCODE") {
+        IrisError::sql(message)
+    } else if message.contains("ERROR #") || has_error_code {
+        IrisError::Server { message }
+    } else {
+        IrisError::Network { message }
+    }
+}
+
+struct NativeBackend {
+    connection: Connection,
+}
+
+impl IrisBackend for NativeBackend {
+    fn server_version(&mut self) -> Result<String, IrisError> {
+        let connection = &mut self.connection;
+        driver_call(|| connection.server_version())
+    }
+
+    fn classmethod(&mut self, class: &str, method: &str) -> Result<String, IrisError> {
+        let connection = &mut self.connection;
+        driver_call(|| connection.classmethod(class, method))
+    }
+
+    fn production_state(&mut self) -> Result<Option<(String, i32)>, IrisError> {
+        let connection = &mut self.connection;
+        driver_call(|| Some(connection.production_state()))
+    }
+
+    fn query<'a>(
+        &'a mut self,
+        statement: &Statement,
+    ) -> Result<Box<dyn ResultSet + 'a>, IrisError> {
+        let connection = &mut self.connection;
+        let rs = driver_call(|| {
+            let mut prepared = connection.prepare(statement.sql().to_owned());
+            prepared.execute(statement.params().to_vec())
+        })?;
+        Ok(Box::new(NativeResultSet(rs)))
+    }
+}
+
+/// Fetching rows can still panic if the socket breaks, which `ResultSet` cannot report
+/// otherwise. The `ConnectionManager` then drops the connection.
+struct NativeResultSet(irisnative::ResultSet);
+
+impl ResultSet for NativeResultSet {
+    fn next(&mut self) -> bool {
+        self.0.next()
+    }
+
+    fn get(&self, index: usize) -> Option<String> {
+        self.0.get(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::TlsSettings;
+    use std::net::{Ipv4Addr, TcpListener};
+
+    #[test]
+    fn tls_is_refused_without_connecting() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let settings = ConnectionSettings {
+            super_host: String::from("localhost"),
+            super_port: listener.local_addr().unwrap().port(),
+            tls: Some(TlsSettings::default()),
+            ..ConnectionSettings::default()
+        };
+
+        let error = NativeConnector.connect(&settings).err().unwrap();
+
+        assert!(matches!(error, IrisError::Unsupported { .. }), "{}", error);
+        let accepted = listener.accept();
+        assert_eq!(
+            accepted.err().map(|why| why.kind()),
+            Some(io::ErrorKind::WouldBlock)
+        );
+    }
+
+    #[test]
+    fn classify_connect_errors() {
+        let error = |kind, message| connect_error(io::Error::new(kind, message), "USER");
+
+        assert!(matches!(
+            error(io::ErrorKind::ConnectionRefused, "refused"),
+            IrisError::Network { .. }
+        ));
+        assert!(matches!(
+            error(io::ErrorKind::Other, "Access Denied"),
+            IrisError::AuthFailed { .. }
+        ));
+        assert_eq!(
+            error(io::ErrorKind::Other, "Namespace USER does not exist"),
+            IrisError::NamespaceNotFound {
+                namespace: String::from("USER")
+            }
+        );
+    }
+
+    #[test]
+    fn classify_driver_panics() {
+        let error = |message: &str| {
+            driver_call(|| panic::panic_any(message.to_owned()))
+                .err()
+                .unwrap()
+        };
+
+        assert_eq!(
+            error("[SQLCODE: <-30>:<Table or view not found>]"),
+            IrisError::Sql {
+                sqlcode: Some(-30),
+                message: String::from("[SQLCODE: <-30>:<Table or view not found>]"),
+            }
+        );
+        assert!(matches!(
+            error("<CLASS DOES NOT EXIST> *Demo.Missing"),
+            IrisError::Server { .. }
+        ));
+        assert!(error("failed to fill whole buffer").is_lost_connection());
+        assert!(driver_call(|| panic!("connection reset"))
+            .err()
+            .unwrap()
+            .is_lost_connection());
+        assert_eq!(driver_call(|| 42), Ok(42));
+    }
+}
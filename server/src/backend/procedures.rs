@@ -0,0 +1,385 @@
+//! Operations that need ObjectScript with arguments or objects, which neither irisnative nor
+//! the Atelier API can call directly. They run as SQL functions in the `IrisLS` schema of the namespace,
+//! which are created the first time they are needed, so every backend that runs queries can
+//! perform them.
+//!
+//! Creating a function needs the `%CREATE_FUNCTION` privilege; users without it get the
+//! SQL error. The names carry a version, so a changed body becomes a new function rather
+//! than running an older definition.
+
+use super::{IrisBackend, IrisError, ItemChange, ProductionAction, Statement};
+use std::time::Duration;
+
+const SCHEMA: &str = "IrisLS";
+
+/// The longest string IRIS returns, which limits the results of the functions.
+const MAX_STRING: u32 = 3_641_144;
+
+struct Function {
+    name: &'static str,
+    params: &'static [&'static str],
+    /// ObjectScript that returns the result
+    body: &'static str,
+}
+
+/// Returns an empty string on success and the error text otherwise.
+const CONTROL_PRODUCTION: Function = Function {
+    name: "ControlProductionV1",
+    params: &["pMethod", "pProduction", "pTimeout", "pForce"],
+    body: r#"
+    if pMethod = "StartProduction" {
+        set sc = ##class(Ens.Director).StartProduction(pProduction)
+    } elseif pMethod = "StopProduction" {
+        set sc = ##class(Ens.Director).StopProduction(pTimeout, pForce)
+    } elseif pMethod = "RestartProduction" {
+        set sc = ##class(Ens.Director).RestartProduction(pTimeout, pForce)
+    } elseif pMethod = "UpdateProduction" {
+        set sc = ##class(Ens.Director).UpdateProduction(pTimeout, pForce)
+    } else {
+        quit "Unknown production action "_pMethod
+    }
+    quit $select($system.Status.IsOK(sc): "", 1: $system.Status.GetErrorText(sc))
+"#,
+};
+
+/// Changes an item and applies the change, then returns like [`CONTROL_PRODUCTION`].
+/// An empty `pName` changes whether the item is enabled, otherwise setting `pName` of `pTarget` is set to `pValue`, or removed with `pRemove`.
+const CHANGE_ITEM: Function = Function {
+    name: "ChangeItemV1",
+    params: &[
+        "pProduction",
+        "pItem",
+        "pEnabled",
+        "pTarget",
+        "pName",
+        "pValue",
+        "pRemove",
+        "pTimeout",
+        "pForce",
+    ],
+    body: r#"
+    set production = ##class(Ens.Config.Production).%OpenId(pProduction, , .sc)
+    quit:'$isobject(production) $system.Status.GetErrorText(sc)
+    set item = production.FindItemByConfigName(pItem)
+    quit:'$isobject(item) "Production "_pProduction_" has no item "_pItem
+    if pName = "" {
+        set item.Enabled = pEnabled
+    } else {
+        set index = ""
+        for i = 1:1:item.Settings.Count() {
+            set setting = item.Settings.GetAt(i)
+            if (setting.Target = pTarget) && (setting.Name = pName) {
+                set index = i
+                quit
+            }
+        }
+        if pRemove {
+            if index '= "" {
+                do item.Settings.RemoveAt(index)
+            }
+        } else {
+            if index = "" {
+                set setting = ##class(Ens.Config.Setting).%New()
+                set setting.Target = pTarget
+                set setting.Name = pName
+                do item.Settings.Insert(setting)
+            } else {
+                set setting = item.Settings.GetAt(index)
+            }
+            set setting.Value = pValue
+        }
+    }
+    set sc = production.%Save()
+    set:$system.Status.IsOK(sc) sc = production.SaveToClass(item)
+    set:$system.Status.IsOK(sc) sc = ##class(Ens.Director).UpdateProduction(pTimeout, pForce)
+    quit $select($system.Status.IsOK(sc): "", 1: $system.Status.GetErrorText(sc))
+"#,
+};
+
+/// See [`IrisBackend::control_production`].
+pub(super) fn control_production<B: IrisBackend + ?Sized>(
+    backend: &mut B,
+    action: ProductionAction,
+    production: &str,
+    timeout: u32,
+    force: bool,
+) -> Result<(), IrisError> {
+    let args = [
+        action.method(),
+        production,
+        &timeout.to_string(),
+        flag(force),
+    ];
+    check(call(backend, &CONTROL_PRODUCTION, &args, timeout)?)
+}
+
+/// Returns the source of a class in UDL, or an empty string if there is no such class.
+const CLASS_SOURCE: Function = Function {
+    name: "ClassSourceV1",
+    params: &["pClass"],
+    body: r#"
+    quit:'##class(%Dictionary.ClassDefinition).%ExistsId(pClass) ""
+    set sc = ##class(%Compiler.UDL.TextServices).GetTextAsString(, pClass, .text)
+    if $system.Status.IsError(sc) {
+        throw ##class(%Exception.StatusException).CreateFromStatus(sc)
+    }
+    quit text
+"#,
+};
+
+/// See [`IrisBackend::change_item`].
+pub(super) fn change_item<B: IrisBackend + ?Sized>(
+    backend: &mut B,
+    production: &str,
+    item: &str,
+    change: &ItemChange,
+    timeout: u32,
+    force: bool,
+) -> Result<(), IrisError> {
+    let timeout_arg = timeout.to_string();
+    let args = match change {
+        ItemChange::Enabled(enabled) => [
+            production,
+            item,
+            flag(*enabled),
+            "",
+            "",
+            "",
+            "0",
+            &timeout_arg,
+            flag(force),
+        ],
+        ItemChange::Setting {
+            target,
+            name,
+            value,
+        } => [
+            production,
+            item,
+            "",
+            target,
+            name,
+            value.as_deref().unwrap_or_default(),
+            flag(value.is_none()),
+            &timeout_arg,
+            flag(force),
+        ],
+    };
+    check(call(backend, &CHANGE_ITEM, &args, timeout)?)
+}
+
+/// The source of class `class` in UDL, line by line, as `.cls` files have it.
+pub(super) fn class_source<B: IrisBackend + ?Sized>(
+    backend: &mut B,
+    class: &str,
+) -> Result<Option<Vec<String>>, IrisError> {
+    let source = call(backend, &CLASS_SOURCE, &[class], 0)?;
+    Ok(Some(source)
+        .filter(|source| !source.is_empty())
+        .map(|source| source.lines().map(str::to_owned).collect()))
+}
+
+fn flag(value: bool) -> &'static str {
+    if value {
+        "1"
+    } else {
+        "0"
+    }
+}
+
+/// Turns the error text that a function returned into an error, unless it is empty.
+fn check(error: String) -> Result<(), IrisError> {
+    if error.is_empty() {
+        Ok(())
+    } else {
+        Err(IrisError::Server { message: error })
+    }
+}
+
+/// Calls `function`, which may run `timeout` seconds longer than a query usually does,
+/// and returns its result. Creates the function first if the namespace does not have it yet.
+fn call<B: IrisBackend + ?Sized>(
+    backend: &mut B,
+    function: &Function,
+    args: &[&str],
+    timeout: u32,
+) -> Result<String, IrisError> {
+    if !exists(backend, function)? {
+        backend.query(&Statement::new(definition(function)))?;
+    }
+
+    let placeholders = vec!["?"; args.len()].join(", ");
+    let sql = format!("SELECT {}.{}({})", SCHEMA, function.name, placeholders);
+    let statement = args
+        .iter()
+        .fold(Statement::new(sql), |statement, arg| statement.bind(*arg))
+        .with_timeout(Duration::from_secs(timeout.into()));
+    let mut rs = backend.query(&statement)?;
+    let result = if rs.next() { rs.get(0) } else { None };
+    Ok(result.unwrap_or_default())
+}
+
+fn exists<B: IrisBackend + ?Sized>(
+    backend: &mut B,
+    function: &Function,
+) -> Result<bool, IrisError> {
+    let mut rs = backend.query(
+        &Statement::new(
+            "SELECT COUNT(*) FROM INFORMATION_SCHEMA.ROUTINES \
+             WHERE ROUTINE_SCHEMA = ? AND ROUTINE_NAME = ?",
+        )
+        .bind(SCHEMA)
+        .bind(function.name),
+    )?;
+    Ok(rs.next() && rs.get(0).is_some_and(|count| count != "0"))
+}
+
+fn definition(function: &Function) -> String {
+    let params: Vec<String> = function
+        .params
+        .iter()
+        .map(|param| format!("{} VARCHAR(32000)", param))
+        .collect();
+    format!(
+        "CREATE FUNCTION {}.{}({}) RETURNS VARCHAR({}) LANGUAGE OBJECTSCRIPT {{{}}}",
+        SCHEMA,
+        function.name,
+        params.join(", "),
+        MAX_STRING,
+        function.body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{FakeIris, Fixtures, QueryFixture};
+
+    fn existing(rows: &str) -> QueryFixture {
+        QueryFixture {
+            matches: String::from("INFORMATION_SCHEMA.ROUTINES"),
+            rows: vec![vec![String::from(rows)]],
+            ..QueryFixture::default()
+        }
+    }
+
+    #[test]
+    fn creates_missing_functions_before_the_call() {
+        let mut backend = FakeIris::new(Fixtures {
+            queries: vec![existing("0")],
+            ..Fixtures::default()
+        });
+
+        control_production(
+            &mut backend,
+            ProductionAction::Stop,
+            "Demo.Production",
+            60,
+            true,
+        )
+        .unwrap();
+
+        let statements = backend.statements();
+        assert_eq!(statements.len(), 3);
+        assert!(statements[1]
+            .sql()
+            .starts_with("CREATE FUNCTION IrisLS.ControlProductionV1(pMethod VARCHAR(32000)"));
+        assert_eq!(
+            statements[2],
+            Statement::new("SELECT IrisLS.ControlProductionV1(?, ?, ?, ?)")
+                .bind("StopProduction")
+                .bind("Demo.Production")
+                .bind("60")
+                .bind("1")
+                .with_timeout(Duration::from_secs(60))
+        );
+    }
+
+    #[test]
+    fn existing_functions_are_called_directly() {
+        let mut backend = FakeIris::new(Fixtures {
+            queries: vec![existing("1")],
+            ..Fixtures::default()
+        });
+
+        control_production(
+            &mut backend,
+            ProductionAction::Start,
+            "Demo.Production",
+            10,
+            false,
+        )
+        .unwrap();
+
+        let statements = backend.statements();
+        assert_eq!(statements.len(), 2);
+        assert_eq!(
+            statements[1].params()[..2],
+            ["StartProduction", "Demo.Production"]
+        );
+    }
+
+    #[test]
+    fn class_source_in_lines() {
+        let mut backend = FakeIris::new(Fixtures {
+            queries: vec![
+                existing("1"),
+                QueryFixture {
+                    matches: String::from("SELECT IrisLS.ClassSourceV1("),
+                    params: Some(vec![String::from("Demo.Service")]),
+                    rows: vec![vec![String::from(
+                        "Class Demo.Service Extends Ens.BusinessService\r\n{\r\n\r\n}",
+                    )]],
+                    ..QueryFixture::default()
+                },
+                QueryFixture {
+                    matches: String::from("SELECT IrisLS.ClassSourceV1("),
+                    rows: vec![vec![String::new()]],
+                    ..QueryFixture::default()
+                },
+            ],
+            ..Fixtures::default()
+        });
+
+        assert_eq!(
+            class_source(&mut backend, "Demo.Service"),
+            Ok(Some(vec![
+                String::from("Class Demo.Service Extends Ens.BusinessService"),
+                String::from("{"),
+                String::new(),
+                String::from("}"),
+            ]))
+        );
+        assert_eq!(class_source(&mut backend, "Demo.Missing"), Ok(None));
+    }
+
+    #[test]
+    fn returned_text_is_the_error() {
+        let mut backend = FakeIris::new(Fixtures {
+            queries: vec![
+                existing("1"),
+                QueryFixture {
+                    matches: String::from("SELECT IrisLS.ControlProductionV1("),
+                    rows: vec![vec![String::from("ERROR #5001: Production is not running")]],
+                    ..QueryFixture::default()
+                },
+            ],
+            ..Fixtures::default()
+        });
+
+        let result = control_production(
+            &mut backend,
+            ProductionAction::Update,
+            "Demo.Production",
+            10,
+            false,
+        );
+
+        assert_eq!(
+            result,
+            Err(IrisError::Server {
+                message: String::from("ERROR #5001: Production is not running")
+            })
+        );
+    }
+}
@@ -1,6 +1,6 @@
 use crate::protocol::*;
 use futures::lock::Mutex;
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 use serde::de::DeserializeOwned;
 use log::{error,warn};
 
@@ -9,6 +9,7 @@ pub struct ConfigManager<C> {
     client: Arc<C>,
     client_capabilities: Arc<ClientCapabilities>,
     options: Mutex<Options>,
+    folder_options: Mutex<HashMap<Url, Options>>,
 }
 
 impl<C: LspClient + Send + Sync + 'static> ConfigManager<C> {
@@ -17,6 +18,7 @@ impl<C: LspClient + Send + Sync + 'static> ConfigManager<C> {
             client,
             client_capabilities,
             options: Mutex::default(),
+            folder_options: Mutex::default(),
         }
     }
 
@@ -24,6 +26,15 @@ impl<C: LspClient + Send + Sync + 'static> ConfigManager<C> {
         self.options.lock().await.clone()
     }
 
+    /// The options for a workspace folder, which fall back to the global options
+    /// when the client did not provide any for that folder.
+    pub async fn get_folder(&self, folder: &Url) -> Options {
+        match self.folder_options.lock().await.get(folder) {
+            Some(options) => options.clone(),
+            None => self.get().await,
+        }
+    }
+
     pub async fn register(&self) {
         if !self.client_capabilities.has_pull_configuration_support()
             && self.client_capabilities.has_push_configuration_support()
@@ -57,36 +68,60 @@ impl<C: LspClient + Send + Sync + 'static> ConfigManager<C> {
         }
     }
 
-    pub async fn pull(&self) -> bool {
+    /// Pulls the global options and the options of every workspace folder.
+    pub async fn pull(&self, folders: &[Url]) -> bool {
         if self.client_capabilities.has_pull_configuration_support() {
-            let objectscript = self.pull_section("objectscript").await;
+            let mut scopes = vec![None];
+            scopes.extend(folders.iter().cloned().map(Some));
+            let mut objectscript = self.pull_section("objectscript", &scopes).await.into_iter();
+            let mut intersystems = self.pull_section("intersystems", &scopes).await.into_iter();
 
             let new_options = Options {
-                objectscript: Some(objectscript),
+                objectscript: objectscript.next(),
+                intersystems: intersystems.next(),
             };
+            let new_folder_options: HashMap<_, _> = folders
+                .iter()
+                .cloned()
+                .zip(objectscript.zip(intersystems).map(|(objectscript, intersystems)| Options {
+                    objectscript: Some(objectscript),
+                    intersystems: Some(intersystems),
+                }))
+                .collect();
+
             let mut old_options = self.options.lock().await;
-            let has_changed = *old_options != new_options;
+            let mut old_folder_options = self.folder_options.lock().await;
+            let has_changed =
+                *old_options != new_options || *old_folder_options != new_folder_options;
             *old_options = new_options;
+            *old_folder_options = new_folder_options;
             has_changed
         } else {
             false
         }
     }
 
-    async fn pull_section<T: DeserializeOwned + Default>(&self, section: &str) -> T {
+    async fn pull_section<T: DeserializeOwned + Default>(
+        &self,
+        section: &str,
+        scopes: &[Option<Url>],
+    ) -> Vec<T> {
         let params = ConfigurationParams {
-            items: vec![ConfigurationItem {
-                section: Some(section.into()),
-                scope_uri: None,
-            }],
+            items: scopes
+                .iter()
+                .map(|scope_uri| ConfigurationItem {
+                    section: Some(section.into()),
+                    scope_uri: scope_uri.as_ref().map(Url::to_string),
+                })
+                .collect(),
         };
 
         match self.client.configuration(params).await {
             Ok(json) => match serde_json::from_value::<Vec<T>>(json) {
-                Ok(config) => config.into_iter().next().unwrap(),
-                Err(_) => {
+                Ok(config) if config.len() == scopes.len() => config,
+                _ => {
                     warn!("Invalid configuration: {}", section);
-                    T::default()
+                    scopes.iter().map(|_| T::default()).collect()
                 }
             },
             Err(why) => {
@@ -94,7 +129,7 @@ impl<C: LspClient + Send + Sync + 'static> ConfigManager<C> {
                     "Retrieving configuration for {} failed: {}",
                     section, why.message
                 );
-                T::default()
+                scopes.iter().map(|_| T::default()).collect()
             }
         }
     }
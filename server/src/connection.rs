@@ -1,187 +1,2444 @@
-use crate::{protocol::*};
-use irisnative::{connection::*, Connection};
-use once_cell::sync::OnceCell;
-use std::sync::Arc;
+use crate::{
+    backend::{
+        ConnectionSettings, IrisBackend, IrisConnector, IrisError, ItemChange, ProductionAction,
+        Statement, TlsSettings,
+    },
+    protocol::*,
+};
+use futures::{
+    future::{self, AbortHandle},
+    lock::{Mutex, MutexGuard},
+};
+use log::{trace, warn};
+use std::{
+    collections::HashMap,
+    mem,
+    path::PathBuf,
+    sync::{Arc, Weak},
+    time::{Duration, Instant},
+};
+use tokio::{task, time};
 
-#[allow(dead_code)]
-struct ConnectionSettings {
-	host: String,
-	port: u16,
-	super_port: u16,
-	ns: String,
-	username: String,
-	password: String,
+/// How long to wait before the first reconnect attempt after a failure.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Upper bound for the delay between reconnect attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+#[derive(Default)]
+struct ConnectionState {
+    settings: Option<ConnectionSettings>,
+    connection: Option<Box<dyn IrisBackend>>,
+    token: Option<String>,
+    failures: u32,
+    retry_at: Option<Instant>,
+    last_error: Option<IrisError>,
+    /// Provided by the client on the first connection attempt and never written anywhere
+    credentials: Option<InterSystemsCredentials>,
+    reported: Option<bool>,
+}
+
+impl ConnectionState {
+    fn backing_off(&self) -> bool {
+        matches!(self.retry_at, Some(retry_at) if Instant::now() < retry_at)
+    }
+}
+
+/// Polls the production status until it is dropped.
+struct Watcher {
+    interval: Duration,
+    handle: AbortHandle,
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
 }
 
 pub struct ConnectionManager<C> {
-	_client: Arc<C>,
-	_connection: OnceCell<Connection>,
-	connection_settings: OnceCell<ConnectionSettings>,
-	token: OnceCell<String>,
+    client: Arc<C>,
+    connector: Arc<dyn IrisConnector>,
+    scope_uri: Option<Url>,
+    state: Mutex<ConnectionState>,
+    /// Held while the client asks the user for credentials, so only one prompt is shown
+    prompt: Mutex<()>,
+    watcher: Mutex<Option<Watcher>>,
 }
 
 impl<C: LspClient + Send + Sync + 'static> ConnectionManager<C> {
-	pub fn new(_client: Arc<C>) -> Self {
-		Self {
-			_client,
-			_connection: OnceCell::new(),
-			connection_settings: OnceCell::new(),
-			token: OnceCell::new(),
-		}
-	}
-
-	pub async fn reparse(&self, options: &Options) {
-		let objectscript = options.objectscript.as_ref().cloned().unwrap_or_default();
-		let conn = objectscript.conn.unwrap_or_default();
-		let host = conn.host.unwrap_or_default();
-		let port = conn.port.unwrap_or_default();
-		let super_port = conn.super_port.unwrap_or_default();
-		let username = conn.username.unwrap_or_default();
-		let password = conn.password.unwrap_or_default();
-		let ns = conn.ns.unwrap_or_default();
-		let active = conn.active.unwrap_or_default()
-			&& !host.is_empty()
-			&& !username.is_empty()
-			&& !password.is_empty()
-			&& !ns.is_empty()
-			&& super_port > 0;
-		if active {
-			// if let Ok(mut connection) = Connection::connect(host, super_port, ns, username, password) {
-			// 	let version = connection.server_version();
-			// 	trace!("Connected to: {}", version);
-			// 	// let _ = self.connection.set(connection);
-			// 	let _ = self
-			// 		.client
-			// 		.connected(InterSystemsConnectedParams { version });
-			// }
-			let _ = self.connection_settings.set(ConnectionSettings {
-				host, port, super_port, ns, username, password
-			});
-		}
-	}
-
-	fn connect(&self) -> Option<Connection> {
-		if let Some(ConnectionSettings {
-			host, super_port, ns, username, password, ..
-		}) = self.connection_settings.get() {
-			if let Ok(connection) = Connection::connect(host.to_owned(), super_port.to_owned(), ns.to_owned(), username.to_owned(), password.to_owned()) {
-				Some(connection)
-			} else {
-				None
-			}
-		} else {
-			None
-		}
-	}
-
-	#[allow(dead_code)]
-	fn token(&self) -> Option<String> {
-		if let Some(token) = self.token.get() {
-			Some(token.to_owned())
-		} else if let Some(mut connection) = self.connect() {
-			let token: String = connection.classmethod("%RoutineMgr", "ConstructCSPSession");
-			if !token.is_empty() {
-				let _ = self.token.set(token.clone());
-				Some(token)
-			} else {
-				None
-			}
-		} else {
-			None
-		}
-	}
-
-	pub async fn productions(&self) -> Vec<Production> {
-		if let Some(mut connection) = self.connect() {
-			let mut list = Vec::new();
-			
-      let (curprod, curstate) = connection.production_state();
-      let curstatus = match curstate { 1 => "Running", 2 => "Stopped", 3 => "Suspended", 4 => "Troubled", _ => "Unknown" };
-
-			let mut rs = connection.query(String::from(
-				"select Name from %Dictionary.ClassDefinition where super = 'Ens.Production' and abstract<>1",
-			));
-			while rs.next() {
-				let id: String = rs.get(0).unwrap();
-        let status = String::from(if id == curprod { curstatus } else { "Stopped" });
-				list.push(Production { id, status });
-			}
-			list
-		} else {
-			Vec::new()
-		}
-	}
-
-	pub async fn production_services(&self, production: String) -> Vec<ProductionService> {
-		if let Some(mut connection) = self.connect() {
-			let mut list = Vec::new();
-			
-			let mut rs = connection.query(format!(
-				"select name from ens_config.item where production='{}'
-        and 
-        classname in (select name from %dictionary.classdefinition where super='Ens.BusinessService')", production
-			));
-			while rs.next() {
-				let id: String = rs.get(0).unwrap();
-				list.push(ProductionService { id });
-			}
-			list
-		} else {
-			Vec::new()
-		}
-	}
-
-	pub async fn production_operations(&self, production: String) -> Vec<ProductionOperation> {
-		if let Some(mut connection) = self.connect() {
-			let mut list = Vec::new();
-			
-			let mut rs = connection.query(format!(
-				"select name from ens_config.item where production='{}'
-        and 
-        classname in (select name from %dictionary.classdefinition where super='Ens.BusinessOperation')", production
-			));
-			while rs.next() {
-				let id: String = rs.get(0).unwrap();
-				list.push(ProductionOperation { id });
-			}
-			list
-		} else {
-			Vec::new()
-		}
-	}
-
-	pub async fn production_processes(&self, production: String) -> Vec<ProductionProcess> {
-		if let Some(mut connection) = self.connect() {
-			let mut list = Vec::new();
-			
-			let mut rs = connection.query(format!(
-				"select name from ens_config.item where production='{}'
-        and 
-        classname in (select name from %dictionary.classdefinition where super='Ens.BusinessProcess')", production
-			));
-			while rs.next() {
-				let id: String = rs.get(0).unwrap();
-				list.push(ProductionProcess { id });
-			}
-			list
-		} else {
-			Vec::new()
-		}
-	}
-
-	pub async fn globals(&self) -> Vec<Global> {
-		if let Some(mut connection) = self.connect() {
-			let mut list = Vec::new();
-			let mut rs = connection.query(String::from(
-				"SELECT DISTINCT '^' || $piece(name,'(',1) Name from %SYS.GlobalQuery_NamespaceList()",
-			));
-			while rs.next() {
-				let name: String = rs.get(0).unwrap();
-				list.push(Global { name });
-			}
-			list
-		} else {
-			Vec::new()
-		}
-	}
+    pub fn new(client: Arc<C>, connector: Arc<dyn IrisConnector>, scope_uri: Option<Url>) -> Self {
+        Self {
+            client,
+            connector,
+            scope_uri,
+            state: Mutex::default(),
+            prompt: Mutex::default(),
+            watcher: Mutex::default(),
+        }
+    }
+
+    pub async fn reparse(&self, options: &Options) {
+        let settings = connection_settings(options);
+
+        let mut state = self.state.lock().await;
+        if state.settings == settings {
+            return;
+        }
+
+        // Everything that belongs to the old target goes: the connection, the token and the
+        // backoff. The credentials stay while the server is the same, e.g. for another namespace.
+        // The connection state is reported again for the new target.
+        trace!("Connection settings changed");
+        let credentials = state
+            .credentials
+            .take()
+            .filter(|_| same_server(state.settings.as_ref(), settings.as_ref()));
+        *state = ConnectionState {
+            settings,
+            credentials,
+            ..ConnectionState::default()
+        };
+        // A new target gets a new watcher, with its own baseline
+        *self.watcher.lock().await = None;
+        if state.settings.is_some() {
+            // Connect right away, so the client learns about the connection state early
+            drop(state);
+            let _ = self.with_connection(|_| Ok(())).await;
+        } else {
+            self.report(&mut state, false, String::new()).await;
+            drop(state);
+        }
+
+        let scope_uri = self.scope_uri.clone();
+        self.client
+            .refresh(InterSystemsRefreshParams { scope_uri })
+            .await;
+    }
+
+    pub async fn close(&self) {
+        if self.state.lock().await.connection.take().is_some() {
+            trace!("Closed connection");
+        }
+    }
+
+    /// Runs `f` on the shared connection, connecting first if necessary.
+    ///
+    /// A call that fails with a network error is treated as a broken socket: the connection
+    /// is dropped and `f` is retried once on a fresh one. Any other error, such as an SQL
+    /// error, is returned as it is and the connection is kept.
+    /// That is only safe for reads, changes go through [`Self::with_connection_once`].
+    /// Failed connection attempts are spaced out with an exponential backoff, during which
+    /// the error of the last attempt is returned right away.
+    ///
+    /// Backend calls block, so `f` runs on the blocking thread pool. Calls on the same
+    /// connection are queued behind the lock, while other messages keep being handled.
+    async fn with_connection<T, F>(&self, f: F) -> Result<T, IrisError>
+    where
+        F: FnMut(&mut dyn IrisBackend) -> Result<T, IrisError> + Send + 'static,
+        T: Send + 'static,
+    {
+        let mut state = self.lock_state().await;
+        self.run(&mut state, true, f).await
+    }
+
+    /// Like [`Self::with_connection`], but `f` is not retried when the connection breaks.
+    /// The call may have reached the server before, and running a change twice, such as
+    /// starting a production, is worse than reporting the broken connection.
+    async fn with_connection_once<T, F>(&self, f: F) -> Result<T, IrisError>
+    where
+        F: FnMut(&mut dyn IrisBackend) -> Result<T, IrisError> + Send + 'static,
+        T: Send + 'static,
+    {
+        let mut state = self.lock_state().await;
+        self.run(&mut state, false, f).await
+    }
+
+    /// Locks the connection state, first asking the client for credentials if connecting
+    /// needs them. The state is not locked while the user answers, so other messages for
+    /// this connection, e.g. a settings change, are handled in the meantime.
+    async fn lock_state(&self) -> MutexGuard<'_, ConnectionState> {
+        let _prompt = self.prompt.lock().await;
+        loop {
+            let state = self.state.lock().await;
+            let settings = match &state.settings {
+                Some(settings)
+                    if state.connection.is_none()
+                        && state.credentials.is_none()
+                        && !state.backing_off() =>
+                {
+                    settings.clone()
+                }
+                _ => return state,
+            };
+            drop(state);
+
+            let credentials = self.credentials(&settings).await;
+            let mut state = self.state.lock().await;
+            if state.settings.as_ref() != Some(&settings) {
+                // Asked for another target, so its answer does not apply
+                continue;
+            }
+            match credentials {
+                Ok(credentials) => state.credentials = Some(credentials),
+                Err(error) => {
+                    self.failed(&mut state, error).await;
+                }
+            }
+            return state;
+        }
+    }
+
+    async fn run<T, F>(
+        &self,
+        state: &mut ConnectionState,
+        retry: bool,
+        mut f: F,
+    ) -> Result<T, IrisError>
+    where
+        F: FnMut(&mut dyn IrisBackend) -> Result<T, IrisError> + Send + 'static,
+        T: Send + 'static,
+    {
+        let mut error = IrisError::NotConfigured;
+        for _ in 0..if retry { 2 } else { 1 } {
+            let mut connection = match state.connection.take() {
+                Some(connection) => connection,
+                None => {
+                    if state.backing_off() {
+                        return Err(state.last_error.clone().unwrap_or(error));
+                    }
+                    self.connect(state).await?;
+                    state.connection.take().ok_or(IrisError::NotConfigured)?
+                }
+            };
+
+            let (returned_f, connection, result) = blocking(move || {
+                let result = f(connection.as_mut());
+                (f, connection, result)
+            })
+            .await
+            .ok_or_else(|| IrisError::network("the backend call was aborted"))?;
+            f = returned_f;
+
+            error = match result {
+                Err(lost) if lost.is_lost_connection() => lost,
+                result => {
+                    state.connection = Some(connection);
+                    return result;
+                }
+            };
+            warn!("Lost connection to the server: {}", error);
+            self.report(state, false, String::new()).await;
+        }
+        Err(error)
+    }
+
+    async fn connect(&self, state: &mut ConnectionState) -> Result<(), IrisError> {
+        let mut settings = state.settings.clone().ok_or(IrisError::NotConfigured)?;
+        // Asked for by `lock_state`, before the connection is needed
+        let connection = match state.credentials.clone() {
+            Some(credentials) => {
+                settings.username = credentials.username;
+                settings.password = credentials.password;
+                self.open(settings).await
+            }
+            None => Err(IrisError::AuthFailed {
+                message: String::from("No credentials were provided"),
+            }),
+        };
+
+        match connection {
+            Ok((connection, version)) => {
+                trace!("Connected to: {}", version);
+                state.connection = Some(connection);
+                state.failures = 0;
+                state.retry_at = None;
+                state.last_error = None;
+                self.report(state, true, version).await;
+                Ok(())
+            }
+            Err(error) => {
+                self.failed(state, error.clone()).await;
+                Err(error)
+            }
+        }
+    }
+
+    /// Records a failed connection attempt and when to try again.
+    async fn failed(&self, state: &mut ConnectionState, error: IrisError) {
+        if matches!(error, IrisError::AuthFailed { .. }) {
+            // Ask again on the next attempt instead of repeating a rejected password
+            state.credentials = None;
+        }
+        state.failures += 1;
+        let delay = backoff(state.failures);
+        warn!(
+            "Failed to connect to the server: {}, retrying in {:?}",
+            error, delay
+        );
+        state.retry_at = Some(Instant::now() + delay);
+        state.last_error = Some(error);
+        self.report(state, false, String::new()).await;
+    }
+
+    async fn credentials(
+        &self,
+        settings: &ConnectionSettings,
+    ) -> Result<InterSystemsCredentials, IrisError> {
+        let (host, port) = match settings.backend {
+            ConnBackend::Native => (settings.super_host.clone(), settings.super_port),
+            ConnBackend::Atelier => (settings.host.clone(), settings.port),
+        };
+        let params = InterSystemsCredentialsParams {
+            scope_uri: self.scope_uri.clone(),
+            server_name: settings.server_name.clone(),
+            host,
+            port,
+            namespace: settings.ns.clone(),
+            username: Some(settings.username.clone()).filter(|username| !username.is_empty()),
+        };
+        match self.client.credentials(params).await {
+            Ok(Some(credentials)) => Ok(credentials),
+            Ok(None) => Err(IrisError::AuthFailed {
+                message: String::from("No credentials were provided"),
+            }),
+            Err(why) => Err(IrisError::AuthFailed {
+                message: format!("Failed to request credentials: {}", why.message),
+            }),
+        }
+    }
+
+    async fn open(
+        &self,
+        settings: ConnectionSettings,
+    ) -> Result<(Box<dyn IrisBackend>, String), IrisError> {
+        let connector = Arc::clone(&self.connector);
+        blocking(move || {
+            let mut connection = connector.connect(&settings)?;
+            let version = connection.server_version()?;
+            Ok((connection, version))
+        })
+        .await
+        .unwrap_or_else(|| Err(IrisError::network("the connection attempt was aborted")))
+    }
+
+    async fn report(&self, state: &mut ConnectionState, connected: bool, version: String) {
+        if state.reported != Some(connected) {
+            state.reported = Some(connected);
+            let scope_uri = self.scope_uri.clone();
+            self.client
+                .connected(InterSystemsConnectedParams {
+                    scope_uri,
+                    connected,
+                    version,
+                })
+                .await;
+        }
+    }
+
+    /// Checks the current production and the status of its business hosts every `interval`
+    /// and sends `intersystems/productionStatusChanged` when they differ from the last check.
+    /// Checks are skipped while there is no connection, so they never ask for credentials,
+    /// and stop with a warning if the backend cannot tell the production state.
+    /// `None` stops checking.
+    pub async fn watch_productions(self: &Arc<Self>, interval: Option<Duration>) {
+        let mut watcher = self.watcher.lock().await;
+        if watcher.as_ref().map(|watcher| watcher.interval) == interval {
+            return;
+        }
+
+        *watcher = interval.map(|interval| {
+            let (poll, handle) =
+                future::abortable(Self::poll_productions(Arc::downgrade(self), interval));
+            tokio::spawn(poll);
+            Watcher { interval, handle }
+        });
+    }
+
+    async fn poll_productions(manager: Weak<Self>, interval: Duration) {
+        let mut last = None;
+        loop {
+            time::delay_for(interval).await;
+            let manager = match manager.upgrade() {
+                Some(manager) => manager,
+                None => break,
+            };
+
+            let params = match manager.production_status_snapshot().await {
+                Ok(Some(params)) => params,
+                Ok(None) => continue,
+                Err(error @ IrisError::Unsupported { .. }) => {
+                    warn!("{}, production status changes are not reported", error);
+                    break;
+                }
+                Err(error) => {
+                    trace!("Failed to check the production status: {}", error);
+                    continue;
+                }
+            };
+            // The first check is the baseline for the tree the client already shows
+            if last.as_ref().is_some_and(|last| *last != params) {
+                manager
+                    .client
+                    .production_status_changed(params.clone())
+                    .await;
+            }
+            last = Some(params);
+        }
+    }
+
+    async fn production_status_snapshot(
+        &self,
+    ) -> Result<Option<InterSystemsProductionStatusChangedParams>, IrisError> {
+        let mut state = self.state.lock().await;
+        if state.connection.is_none() {
+            return Ok(None);
+        }
+
+        let scope_uri = self.scope_uri.clone();
+        self.run(&mut state, true, move |connection| {
+            let state = connection.production_state()?;
+            if state.is_none() {
+                return Err(IrisError::Unsupported {
+                    operation: String::from("Reading the production state"),
+                });
+            }
+            let production = match &state {
+                Some((id, _)) if !id.is_empty() => Some(Production {
+                    id: id.clone(),
+                    status: production_status(&state, id),
+                }),
+                _ => None,
+            };
+            let hosts = match production {
+                Some(_) => rows(
+                    connection,
+                    &Statement::new(
+                        "select Name, Status from Ens_Util.Statistics_EnumerateHostStatus()",
+                    ),
+                    2,
+                )?
+                .into_iter()
+                .map(|row| {
+                    let mut row = row.into_iter();
+                    let name = row.next().unwrap_or_default();
+                    ProductionHostStatus {
+                        name,
+                        status: row.next().unwrap_or_default(),
+                    }
+                })
+                .collect(),
+                None => Vec::new(),
+            };
+            Ok(Some(InterSystemsProductionStatusChangedParams {
+                scope_uri: scope_uri.clone(),
+                production,
+                hosts,
+            }))
+        })
+        .await
+    }
+
+    #[allow(dead_code)]
+    async fn token(&self) -> Result<Option<String>, IrisError> {
+        let mut state = self.lock_state().await;
+        if let Some(token) = &state.token {
+            return Ok(Some(token.to_owned()));
+        }
+
+        let token = self
+            .run(&mut state, true, |connection| {
+                connection.classmethod("%RoutineMgr", "ConstructCSPSession")
+            })
+            .await?;
+        if !token.is_empty() {
+            state.token = Some(token.clone());
+            Ok(Some(token))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub async fn productions(&self) -> Result<Vec<Production>, IrisError> {
+        self.with_connection(|connection| {
+            let mut list = Vec::new();
+
+            let state = connection.production_state()?;
+            let mut rs = connection.query(
+                &Statement::new(
+                    "select Name from %Dictionary.ClassDefinition where super = ? and abstract<>1",
+                )
+                .bind("Ens.Production"),
+            )?;
+            while rs.next() {
+                let id = rs.get(0).unwrap_or_default();
+                let status = production_status(&state, &id);
+                list.push(Production { id, status });
+            }
+            Ok(list)
+        })
+        .await
+    }
+
+    /// Starts, stops, restarts or updates `production` through `Ens.Director` and returns its
+    /// status afterwards. Only the current production can be stopped, restarted or updated.
+    /// IRIS waits `timeout` seconds for business hosts to finish, and with `force` it stops
+    /// those that do not.
+    pub async fn control_production(
+        &self,
+        action: ProductionAction,
+        production: String,
+        timeout: u32,
+        force: bool,
+    ) -> Result<Production, IrisError> {
+        self.with_connection_once(move |connection| {
+            if action != ProductionAction::Start {
+                if let Some((current, _)) = connection.production_state()? {
+                    if current != production {
+                        return Err(IrisError::Server {
+                            message: format!(
+                                "{} is not the current production, {} is",
+                                production, current
+                            ),
+                        });
+                    }
+                }
+            }
+
+            connection.control_production(action, &production, timeout, force)?;
+
+            let state = connection.production_state()?;
+            let status = production_status(&state, &production);
+            Ok(Production {
+                id: production.clone(),
+                status,
+            })
+        })
+        .await
+    }
+
+    pub async fn production_services(
+        &self,
+        production: String,
+    ) -> Result<Vec<ProductionService>, IrisError> {
+        Ok(self
+            .production_items(&production, "Ens.BusinessService")
+            .await?
+            .into_iter()
+            .map(|id| ProductionService { id })
+            .collect())
+    }
+
+    pub async fn production_operations(
+        &self,
+        production: String,
+    ) -> Result<Vec<ProductionOperation>, IrisError> {
+        Ok(self
+            .production_items(&production, "Ens.BusinessOperation")
+            .await?
+            .into_iter()
+            .map(|id| ProductionOperation { id })
+            .collect())
+    }
+
+    pub async fn production_processes(
+        &self,
+        production: String,
+    ) -> Result<Vec<ProductionProcess>, IrisError> {
+        Ok(self
+            .production_items(&production, "Ens.BusinessProcess")
+            .await?
+            .into_iter()
+            .map(|id| ProductionProcess { id })
+            .collect())
+    }
+
+    async fn production_items(
+        &self,
+        production: &str,
+        superclass: &'static str,
+    ) -> Result<Vec<String>, IrisError> {
+        let production = production.to_owned();
+        self.with_connection(move |connection| {
+            let mut list = Vec::new();
+
+            let mut rs = connection.query(
+                &Statement::new(
+                    "select name from ens_config.item where production = ?
+					and
+					classname in (select name from %dictionary.classdefinition where super = ?)",
+                )
+                .bind(production.as_str())
+                .bind(superclass),
+            )?;
+            while rs.next() {
+                let id = rs.get(0).unwrap_or_default();
+                list.push(id);
+            }
+            Ok(list)
+        })
+        .await
+    }
+
+    /// Reads the configuration of item `name` in `production` from `Ens_Config.Item`. Settings
+    /// come from the `SETTINGS` parameters of the host and adapter classes, with their configured
+    /// values and the initial values of their properties, followed by any other configured
+    /// settings.
+    pub async fn production_item(
+        &self,
+        production: String,
+        name: String,
+    ) -> Result<ProductionItem, IrisError> {
+        self.with_connection(move |connection| {
+            let row = find_item(connection, &production, &name)?;
+            let (id, class_name) = (&row[0], &row[1]);
+            let adapter_class = class_parameter(connection, class_name, "ADAPTER")?
+                .filter(|adapter| !adapter.is_empty());
+
+            let mut configured = rows(
+                connection,
+                &Statement::new(
+                    "select Settings_Name, Settings_Target, Settings_Value \
+                     from Ens_Config.Item_Settings where Item = ?",
+                )
+                .bind(id.as_str()),
+                3,
+            )?;
+            let mut settings = Vec::new();
+            let adapter_target = adapter_class.as_ref().map(|adapter| ("Adapter", adapter));
+            let targets = Some(("Host", class_name)).into_iter().chain(adapter_target);
+            for (target, class) in targets {
+                let defaults = property_defaults(connection, class)?;
+                for setting in setting_names(connection, class)? {
+                    let position = configured
+                        .iter()
+                        .position(|row| row[0] == setting && row[1] == target);
+                    let value = position.map(|position| configured.remove(position)[2].clone());
+                    let default_value = defaults.get(&setting).cloned();
+                    settings.push(ProductionItemSetting {
+                        name: setting,
+                        target: target.to_owned(),
+                        value,
+                        default_value,
+                    });
+                }
+            }
+            settings.extend(configured.into_iter().map(|row| {
+                let mut row = row.into_iter();
+                let name = row.next().unwrap_or_default();
+                let target = row.next().unwrap_or_default();
+                ProductionItemSetting {
+                    name,
+                    target,
+                    value: row.next(),
+                    default_value: None,
+                }
+            }));
+
+            Ok(ProductionItem {
+                name: name.clone(),
+                class_name: class_name.clone(),
+                category: row[2].clone(),
+                enabled: row[3] == "1",
+                pool_size: row[4].parse().ok(),
+                adapter_class,
+                comment: row[5].clone(),
+                settings,
+            })
+        })
+        .await
+    }
+
+    /// Enables or disables item `name` of the running `production` and applies the change
+    /// with a production update, which waits `timeout` seconds for hosts that have to stop.
+    /// See [`IrisBackend::change_item`].
+    pub async fn enable_production_item(
+        &self,
+        production: String,
+        name: String,
+        enabled: bool,
+        timeout: u32,
+        force: bool,
+    ) -> Result<ProductionItem, IrisError> {
+        let (item_production, item_name) = (production.clone(), name.clone());
+        self.with_connection_once(move |connection| {
+            check_running(connection, &production)?;
+            find_item(connection, &production, &name)?;
+            connection.change_item(
+                &production,
+                &name,
+                &ItemChange::Enabled(enabled),
+                timeout,
+                force,
+            )
+        })
+        .await?;
+        self.production_item(item_production, item_name).await
+    }
+
+    /// Sets `setting` of item `name` of the running `production` to `value`, or back to its
+    /// default with `None`, and applies the change with a production update. The setting must
+    /// be declared in the `SETTINGS` of the host class, its adapter or their superclasses;
+    /// `target` picks the host or the adapter, otherwise the host comes first.
+    /// See [`IrisBackend::change_item`].
+    #[allow(clippy::too_many_arguments)]
+    pub async fn set_production_item_setting(
+        &self,
+        production: String,
+        name: String,
+        target: Option<String>,
+        setting: String,
+        value: Option<String>,
+        timeout: u32,
+        force: bool,
+    ) -> Result<ProductionItem, IrisError> {
+        let (item_production, item_name) = (production.clone(), name.clone());
+        self.with_connection_once(move |connection| {
+            check_running(connection, &production)?;
+            let class_name = find_item(connection, &production, &name)?.swap_remove(1);
+
+            let mut classes = vec![("Host", class_name.clone())];
+            if let Some(adapter) = class_parameter(connection, &class_name, "ADAPTER")?
+                .filter(|adapter| !adapter.is_empty())
+            {
+                classes.push(("Adapter", adapter));
+            }
+            let mut known = Vec::new();
+            let mut found = None;
+            for (class_target, class) in classes {
+                let names = setting_names(connection, &class)?;
+                let matches = target
+                    .as_deref()
+                    .is_none_or(|target| target == class_target);
+                if found.is_none() && matches && names.contains(&setting) {
+                    found = Some(class_target);
+                }
+                known.extend(names);
+            }
+            let found = found.ok_or_else(|| IrisError::UnknownSetting {
+                item: name.clone(),
+                setting: setting.clone(),
+                known,
+            })?;
+
+            let change = ItemChange::Setting {
+                target: found.to_owned(),
+                name: setting.clone(),
+                value: value.clone(),
+            };
+            connection.change_item(&production, &name, &change, timeout, force)
+        })
+        .await?;
+        self.production_item(item_production, item_name).await
+    }
+
+    /// Reads the source of a production class and of the host classes its items use.
+    /// Classes that ship with IRIS are listed as skipped instead, and so are classes
+    /// without source. See [`IrisBackend::document`].
+    pub async fn production_sources(
+        &self,
+        production: String,
+    ) -> Result<ProductionSources, IrisError> {
+        self.with_connection(move |connection| {
+            let lines = connection
+                .document(&format!("{}.cls", production))?
+                .ok_or_else(|| IrisError::Server {
+                    message: format!("Production class {} does not exist", production),
+                })?;
+
+            let mut classes = Vec::new();
+            let mut rs = connection.query(
+                &Statement::new(
+                    "select distinct ClassName from Ens_Config.Item \
+                     where Production = ? order by ClassName",
+                )
+                .bind(production.as_str()),
+            )?;
+            while rs.next() {
+                classes.push(rs.get(0).unwrap_or_default());
+            }
+            drop(rs);
+
+            let mut hosts = Vec::new();
+            let mut skipped = Vec::new();
+            for name in classes {
+                if is_system_class(&name) {
+                    skipped.push(name);
+                    continue;
+                }
+                match connection.document(&format!("{}.cls", name))? {
+                    Some(lines) => hosts.push(ClassSource { name, lines }),
+                    None => skipped.push(name),
+                }
+            }
+
+            let production = ClassSource {
+                name: production.clone(),
+                lines,
+            };
+            Ok(ProductionSources {
+                production,
+                hosts,
+                skipped,
+            })
+        })
+        .await
+    }
+
+    pub async fn globals(&self) -> Result<Vec<Global>, IrisError> {
+        self.with_connection(|connection| {
+            let mut list = Vec::new();
+            let mut rs = connection.query(&Statement::new(
+                "SELECT DISTINCT '^' || $piece(name,'(',1) Name from %SYS.GlobalQuery_NamespaceList()",
+            ))?;
+            while rs.next() {
+                let name = rs.get(0).unwrap_or_default();
+                list.push(Global { name });
+            }
+            Ok(list)
+        })
+        .await
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ClassSource {
+    pub name: String,
+    pub lines: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ProductionSources {
+    pub production: ClassSource,
+    pub hosts: Vec<ClassSource>,
+    /// Host classes that were not read: those of IRIS itself and those without source
+    pub skipped: Vec<String>,
+}
+
+/// The `ID`, `ClassName`, `Category`, `Enabled`, `PoolSize` and `Comment` of item `name`
+/// in `production`.
+fn find_item(
+    connection: &mut dyn IrisBackend,
+    production: &str,
+    name: &str,
+) -> Result<Vec<String>, IrisError> {
+    let statement = Statement::new(
+        "select ID, ClassName, Category, Enabled, PoolSize, Comment from Ens_Config.Item \
+         where Production = ? and Name = ?",
+    )
+    .bind(production)
+    .bind(name);
+    rows(connection, &statement, 6)?
+        .pop()
+        .ok_or_else(|| IrisError::Server {
+            message: format!("Production {} has no item {}", production, name),
+        })
+}
+
+/// Fails unless `production` is the running production, where the backend can tell.
+fn check_running(connection: &mut dyn IrisBackend, production: &str) -> Result<(), IrisError> {
+    match connection.production_state()? {
+        Some((current, 1)) if current == production => Ok(()),
+        Some(_) => Err(IrisError::Server {
+            message: format!("{} is not running", production),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Runs `statement` and reads `columns` columns of every row.
+fn rows(
+    connection: &mut dyn IrisBackend,
+    statement: &Statement,
+    columns: usize,
+) -> Result<Vec<Vec<String>>, IrisError> {
+    let mut rows = Vec::new();
+    let mut rs = connection.query(statement)?;
+    while rs.next() {
+        rows.push(
+            (0..columns)
+                .map(|index| rs.get(index).unwrap_or_default())
+                .collect(),
+        );
+    }
+    Ok(rows)
+}
+
+/// The value of class parameter `name` of compiled class `class`, if it has one.
+fn class_parameter(
+    connection: &mut dyn IrisBackend,
+    class: &str,
+    name: &str,
+) -> Result<Option<String>, IrisError> {
+    let statement = Statement::new(
+        "select _Default from %Dictionary.CompiledParameter where parent = ? and Name = ?",
+    )
+    .bind(class)
+    .bind(name);
+    Ok(rows(connection, &statement, 1)?
+        .pop()
+        .and_then(|row| row.into_iter().next()))
+}
+
+/// The settings of a host or adapter class. Every class in its hierarchy can declare settings
+/// in its own `SETTINGS` parameter, such as `FilePath:Basic,FileSpec:Basic:selector,-ArchivePath`,
+/// where a name with a `-` removes a setting that a superclass declared.
+fn setting_names(connection: &mut dyn IrisBackend, class: &str) -> Result<Vec<String>, IrisError> {
+    let mut hierarchy = Vec::new();
+    superclasses_first(connection, class, &mut hierarchy)?;
+
+    let mut names: Vec<String> = Vec::new();
+    for class in hierarchy {
+        let statement = Statement::new(
+            "select _Default from %Dictionary.ParameterDefinition where parent = ? and Name = ?",
+        )
+        .bind(class)
+        .bind("SETTINGS");
+        let parameter = rows(connection, &statement, 1)?
+            .pop()
+            .and_then(|row| row.into_iter().next())
+            .unwrap_or_default();
+        for entry in parameter
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            let name = entry.split(':').next().unwrap_or_default();
+            match name.strip_prefix('-') {
+                Some(removed) => names.retain(|name| name != removed),
+                None if !names.iter().any(|known| known == name) => names.push(name.to_owned()),
+                None => {}
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// Adds the superclasses of `class` to `hierarchy`, each after its own superclasses,
+/// and then `class`.
+fn superclasses_first(
+    connection: &mut dyn IrisBackend,
+    class: &str,
+    hierarchy: &mut Vec<String>,
+) -> Result<(), IrisError> {
+    if hierarchy.iter().any(|known| known == class) {
+        return Ok(());
+    }
+    let statement =
+        Statement::new("select Super from %Dictionary.ClassDefinition where Name = ?").bind(class);
+    let super_classes = rows(connection, &statement, 1)?
+        .pop()
+        .and_then(|row| row.into_iter().next())
+        .unwrap_or_default();
+    for super_class in super_classes
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+    {
+        superclasses_first(connection, &qualified_class(super_class, class), hierarchy)?;
+    }
+    hierarchy.push(class.to_owned());
+    Ok(())
+}
+
+/// The full name of a superclass named `name` in `class`: `%RegisteredObject` is in `%Library`,
+/// and other names without a package are in the package of `class`.
+fn qualified_class(name: &str, class: &str) -> String {
+    if name.contains('.') {
+        return name.to_owned();
+    }
+    match name.strip_prefix('%') {
+        Some(name) => format!("%Library.{}", name),
+        None => match class.rsplit_once('.') {
+            Some((package, _)) => format!("{}.{}", package, name),
+            None => name.to_owned(),
+        },
+    }
+}
+
+/// The initial values of the properties of `class`, with string literals unquoted.
+fn property_defaults(
+    connection: &mut dyn IrisBackend,
+    class: &str,
+) -> Result<HashMap<String, String>, IrisError> {
+    let statement = Statement::new(
+        "select Name, InitialExpression from %Dictionary.CompiledProperty where parent = ?",
+    )
+    .bind(class);
+    Ok(rows(connection, &statement, 2)?
+        .into_iter()
+        .filter(|row| !row[1].is_empty())
+        .map(|row| {
+            let expression = &row[1];
+            let value = match expression
+                .strip_prefix('"')
+                .and_then(|rest| rest.strip_suffix('"'))
+            {
+                Some(literal) => literal.replace("\"\"", "\""),
+                None => expression.clone(),
+            };
+            (row[0].clone(), value)
+        })
+        .collect())
+}
+
+/// Whether `class` ships with IRIS rather than belonging to the application.
+fn is_system_class(class: &str) -> bool {
+    class.starts_with('%')
+        || ["Ens.", "EnsLib.", "EnsPortal."]
+            .iter()
+            .any(|package| class.starts_with(package))
+}
+
+/// The status of production `id`, given the current production and its state
+/// from `Ens.Director`, if the backend can tell.
+fn production_status(state: &Option<(String, i32)>, id: &str) -> String {
+    let status = match state {
+        Some((current, state)) if current == id => match state {
+            1 => "Running",
+            2 => "Stopped",
+            3 => "Suspended",
+            4 => "Troubled",
+            _ => "Unknown",
+        },
+        Some(_) => "Stopped",
+        None => "Unknown",
+    };
+    String::from(status)
+}
+
+/// The connections of a workspace: one for the global settings and one for every
+/// workspace folder whose settings differ from them.
+pub struct WorkspaceConnections<C> {
+    client: Arc<C>,
+    connector: Arc<dyn IrisConnector>,
+    default: Arc<ConnectionManager<C>>,
+    folders: Mutex<HashMap<Url, Arc<ConnectionManager<C>>>>,
+}
+
+impl<C: LspClient + Send + Sync + 'static> WorkspaceConnections<C> {
+    pub fn new(client: Arc<C>, connector: Arc<dyn IrisConnector>) -> Self {
+        let default = ConnectionManager::new(Arc::clone(&client), Arc::clone(&connector), None);
+        Self {
+            client,
+            connector,
+            default: Arc::new(default),
+            folders: Mutex::default(),
+        }
+    }
+
+    /// The connection for a workspace folder, or the global one if `folder` is `None`
+    /// or has no settings of its own.
+    pub async fn get(&self, folder: Option<&Url>) -> Arc<ConnectionManager<C>> {
+        let folders = self.folders.lock().await;
+        let manager = folder.and_then(|folder| folders.get(folder));
+        Arc::clone(manager.unwrap_or(&self.default))
+    }
+
+    pub async fn reparse(&self, options: &Options, folder_options: Vec<(Url, Options)>) {
+        self.default.reparse(options).await;
+        self.default.watch_productions(poll_interval(options)).await;
+
+        let mut folders = self.folders.lock().await;
+        let mut old_folders = mem::take(&mut *folders);
+        for (folder, folder_options) in folder_options {
+            if folder_options == *options {
+                continue;
+            }
+
+            let manager = old_folders.remove(&folder).unwrap_or_else(|| {
+                let client = Arc::clone(&self.client);
+                let connector = Arc::clone(&self.connector);
+                Arc::new(ConnectionManager::new(
+                    client,
+                    connector,
+                    Some(folder.clone()),
+                ))
+            });
+            manager.reparse(&folder_options).await;
+            manager
+                .watch_productions(poll_interval(&folder_options))
+                .await;
+            folders.insert(folder, manager);
+        }
+
+        for manager in old_folders.values() {
+            manager.close().await;
+        }
+    }
+
+    pub async fn close(&self) {
+        self.default.close().await;
+        for manager in self.folders.lock().await.values() {
+            manager.close().await;
+        }
+    }
+}
+
+/// Resolves the connection settings. Host, ports and username come from the server definition
+/// in `intersystems.servers` that `objectscript.conn.server` names, or from `objectscript.conn`.
+/// The password is never taken from the settings; it is requested from the client on connect.
+fn connection_settings(options: &Options) -> Option<ConnectionSettings> {
+    let objectscript = options.objectscript.as_ref().cloned().unwrap_or_default();
+    let conn = objectscript.conn.unwrap_or_default();
+    if !conn.active.unwrap_or_default() {
+        return None;
+    }
+    if conn.password.is_some() {
+        warn!("Ignoring objectscript.conn.password, passwords are requested from the client");
+    }
+
+    let backend = conn.backend.unwrap_or_default();
+    let ns = conn.ns.unwrap_or_default();
+    let tls = conn
+        .tls
+        .filter(|tls| tls.enabled.unwrap_or_default())
+        .map(|tls| TlsSettings {
+            ca_file: tls.ca_file.map(PathBuf::from),
+            cert_file: tls.cert_file.map(PathBuf::from),
+            key_file: tls.key_file.map(PathBuf::from),
+            verify_hostname: tls.verify_hostname.unwrap_or(true),
+        });
+    let settings = match &conn.server {
+        Some(name) => {
+            let definition = options
+                .intersystems
+                .as_ref()
+                .and_then(|intersystems| intersystems.server(name));
+            let definition = match definition {
+                Some(definition) => definition.clone(),
+                None => {
+                    warn!("Server \"{}\" is not defined in intersystems.servers", name);
+                    return None;
+                }
+            };
+            let web_server = definition.web_server.unwrap_or_default();
+            let super_server = definition.super_server.unwrap_or_default();
+            let host = web_server.host.unwrap_or_default();
+            ConnectionSettings {
+                backend,
+                server_name: Some(name.clone()),
+                super_host: super_server.host.unwrap_or_else(|| host.clone()),
+                host,
+                port: web_server.port.unwrap_or_default(),
+                super_port: super_server.port.unwrap_or_default(),
+                https: web_server.scheme.as_deref() == Some("https"),
+                tls,
+                path_prefix: web_server.path_prefix.unwrap_or_default(),
+                ns,
+                username: definition.username.unwrap_or_default(),
+                password: String::new(),
+            }
+        }
+        None => {
+            let host = conn.host.unwrap_or_default();
+            ConnectionSettings {
+                backend,
+                server_name: None,
+                super_host: host.clone(),
+                host,
+                port: conn.port.unwrap_or_default(),
+                super_port: conn.super_port.unwrap_or_default(),
+                https: conn.https.unwrap_or_default(),
+                tls,
+                path_prefix: String::new(),
+                ns,
+                username: conn.username.unwrap_or_default(),
+                password: String::new(),
+            }
+        }
+    };
+
+    let reachable = match backend {
+        ConnBackend::Native => !settings.super_host.is_empty() && settings.super_port > 0,
+        ConnBackend::Atelier => !settings.host.is_empty() && settings.port > 0,
+    };
+    Some(settings).filter(|settings| reachable && !settings.ns.is_empty())
+}
+
+/// How often to check the production status, from `intersystems.productions.pollInterval`.
+fn poll_interval(options: &Options) -> Option<Duration> {
+    let productions = options.intersystems.as_ref()?.productions.as_ref()?;
+    productions
+        .poll_interval
+        .filter(|seconds| *seconds > 0)
+        .map(Duration::from_secs)
+}
+
+/// Whether both settings log in to the same server as the same user.
+fn same_server(old: Option<&ConnectionSettings>, new: Option<&ConnectionSettings>) -> bool {
+    match (old, new) {
+        (Some(old), Some(new)) => {
+            old.backend == new.backend
+                && old.server_name == new.server_name
+                && old.host == new.host
+                && old.port == new.port
+                && old.super_host == new.super_host
+                && old.super_port == new.super_port
+                && old.username == new.username
+        }
+        _ => false,
+    }
+}
+
+/// Runs a blocking backend call on the blocking thread pool.
+async fn blocking<T, F>(f: F) -> Option<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    task::spawn_blocking(f).await.ok()
+}
+
+fn backoff(failures: u32) -> Duration {
+    INITIAL_BACKOFF
+        .checked_mul(1 << failures.saturating_sub(1).min(16))
+        .map_or(MAX_BACKOFF, |delay| delay.min(MAX_BACKOFF))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{FakeIris, Fixtures, QueryFixture};
+    use futures::{channel::mpsc, SinkExt, StreamExt};
+    use jsonrpc::{Id, Response, ResponseHandler};
+    use serde_json::{json, Value};
+    use std::sync::Mutex as StdMutex;
+
+    const HOSTILE_IDS: &[&str] = &[
+        "Demo.Production' or '1'='1",
+        "Demo.Production'; drop table Ens_Config.Item; --",
+        "O'Brien.Production",
+        "Demo.Production\\' or 1=1 --",
+        "' union select Name from %Dictionary.ClassDefinition --",
+        "?",
+    ];
+
+    fn fixtures() -> Fixtures {
+        Fixtures {
+            version: String::from("IRIS for UNIX 2020.1"),
+            production_state: (String::from("Demo.Production"), 1),
+            queries: vec![
+                QueryFixture {
+                    matches: String::from("from %Dictionary.ClassDefinition"),
+                    params: Some(vec![String::from("Ens.Production")]),
+                    rows: vec![
+                        vec![String::from("Demo.Production")],
+                        vec![String::from("Other.Production")],
+                    ],
+                    ..QueryFixture::default()
+                },
+                QueryFixture {
+                    matches: String::from("from ens_config.item"),
+                    params: Some(vec![
+                        String::from(HOSTILE_IDS[0]),
+                        String::from("Ens.BusinessService"),
+                    ]),
+                    rows: vec![vec![String::from("Hostile.Service")]],
+                    ..QueryFixture::default()
+                },
+                QueryFixture {
+                    matches: String::from("GlobalQuery_NamespaceList"),
+                    params: None,
+                    rows: vec![vec![String::from("^Demo")]],
+                    ..QueryFixture::default()
+                },
+            ],
+            ..Fixtures::default()
+        }
+    }
+
+    /// The arguments of every call to SQL function `name`, in order.
+    fn function_calls(backend: &FakeIris, name: &str) -> Vec<Vec<String>> {
+        let call = format!("SELECT IrisLS.{}(", name);
+        backend
+            .statements()
+            .into_iter()
+            .filter(|statement| statement.sql().starts_with(&call))
+            .map(|statement| statement.params().to_vec())
+            .collect()
+    }
+
+    fn options(ns: &str) -> Options {
+        let conn = Conn {
+            active: Some(true),
+            host: Some(String::from("localhost")),
+            super_port: Some(1972),
+            ns: Some(String::from(ns)),
+            username: Some(String::from("_SYSTEM")),
+            ..Conn::default()
+        };
+        Options {
+            objectscript: Some(Objectscript { conn: Some(conn) }),
+            intersystems: None,
+        }
+    }
+
+    fn credentials(password: &str) -> InterSystemsCredentials {
+        InterSystemsCredentials {
+            username: String::from("_SYSTEM"),
+            password: String::from(password),
+        }
+    }
+
+    type CredentialRequests = Arc<StdMutex<Vec<Value>>>;
+
+    /// A client that answers `intersystems/credentials` with `answer` and records the params
+    /// of those requests. Every other message is passed on to the returned receiver.
+    fn client(
+        answer: Option<InterSystemsCredentials>,
+    ) -> (
+        Arc<InterSystemsLspClient>,
+        mpsc::Receiver<String>,
+        CredentialRequests,
+    ) {
+        let (output_tx, mut output_rx) = mpsc::channel::<String>(16);
+        let (mut forward_tx, forward_rx) = mpsc::channel(16);
+        let client = Arc::new(InterSystemsLspClient::new(output_tx));
+        let requests = CredentialRequests::default();
+
+        let responder = Arc::clone(&client);
+        let recorded = Arc::clone(&requests);
+        tokio::spawn(async move {
+            while let Some(json) = output_rx.next().await {
+                let message: Value = serde_json::from_str(&json).unwrap();
+                if message["method"] == "intersystems/credentials" {
+                    recorded.lock().unwrap().push(message["params"].clone());
+                    let id: Id = serde_json::from_value(message["id"].clone()).unwrap();
+                    responder.handle(Response::result(json!(answer), id)).await;
+                } else if forward_tx.send(json).await.is_err() {
+                    break;
+                }
+            }
+        });
+        (client, forward_rx, requests)
+    }
+
+    async fn setup(
+        backend: &FakeIris,
+    ) -> (
+        ConnectionManager<InterSystemsLspClient>,
+        mpsc::Receiver<String>,
+    ) {
+        let (client, output_rx, _) = client(Some(credentials("SYS")));
+        let manager = ConnectionManager::new(client, Arc::new(backend.clone()), None);
+        manager.reparse(&options("USER")).await;
+        (manager, output_rx)
+    }
+
+    /// Collects the messages sent so far. The responder forwards them on its own task,
+    /// so this waits briefly for the last one.
+    async fn notifications(output_rx: &mut mpsc::Receiver<String>) -> Vec<String> {
+        let mut notifications = Vec::new();
+        while let Ok(Some(json)) =
+            tokio::time::timeout(Duration::from_millis(50), output_rx.next()).await
+        {
+            let message: Value = serde_json::from_str(&json).unwrap();
+            let method = message["method"].as_str().unwrap();
+            notifications.push(match message["params"]["connected"].as_bool() {
+                Some(connected) => format!("{} {}", method, connected),
+                None => method.to_owned(),
+            });
+        }
+        notifications
+    }
+
+    async fn connected_notifications(output_rx: &mut mpsc::Receiver<String>) -> Vec<bool> {
+        notifications(output_rx)
+            .await
+            .iter()
+            .filter_map(|notification| notification.strip_prefix("intersystems/connected "))
+            .map(|connected| connected == "true")
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn productions_reuse_connection() {
+        let backend = FakeIris::new(fixtures());
+        let (manager, mut output_rx) = setup(&backend).await;
+
+        let productions = manager.productions().await.unwrap();
+        manager.productions().await.unwrap();
+
+        assert_eq!(
+            productions,
+            vec![
+                Production {
+                    id: String::from("Demo.Production"),
+                    status: String::from("Running")
+                },
+                Production {
+                    id: String::from("Other.Production"),
+                    status: String::from("Stopped")
+                },
+            ]
+        );
+        assert_eq!(backend.connections(), 1);
+        assert_eq!(connected_notifications(&mut output_rx).await, vec![true]);
+    }
+
+    #[tokio::test]
+    async fn reconnect_after_broken_connection() {
+        let backend = FakeIris::new(fixtures());
+        let (manager, mut output_rx) = setup(&backend).await;
+
+        backend.break_connection();
+        let globals = manager.globals().await;
+
+        assert_eq!(
+            globals,
+            Ok(vec![Global {
+                name: String::from("^Demo")
+            }])
+        );
+        assert_eq!(backend.connections(), 2);
+        assert_eq!(
+            connected_notifications(&mut output_rx).await,
+            vec![true, false, true]
+        );
+    }
+
+    #[tokio::test]
+    async fn refused_connection_backs_off() {
+        let backend = FakeIris::new(fixtures());
+        backend.refuse_connections(true);
+        let (manager, mut output_rx) = setup(&backend).await;
+
+        backend.refuse_connections(false);
+        let productions = manager.productions().await;
+
+        assert_eq!(productions, Err(IrisError::network("connection refused")));
+        assert_eq!(backend.connections(), 0);
+        assert_eq!(connected_notifications(&mut output_rx).await, vec![false]);
+    }
+
+    #[tokio::test]
+    async fn rejected_login_is_auth_failure() {
+        let backend = FakeIris::new(fixtures());
+        let error = IrisError::AuthFailed {
+            message: String::from("Access Denied"),
+        };
+        backend.reject_connections(Some(error.clone()));
+        let (manager, mut output_rx) = setup(&backend).await;
+
+        assert_eq!(manager.productions().await, Err(error.clone()));
+        assert_eq!(manager.globals().await, Err(error));
+        assert_eq!(connected_notifications(&mut output_rx).await, vec![false]);
+    }
+
+    #[tokio::test]
+    async fn sql_error_keeps_connection() {
+        let error = IrisError::sql("SQLCODE: -99 Message: Privilege violation");
+        let mut fixtures = fixtures();
+        fixtures.queries.insert(
+            0,
+            QueryFixture {
+                matches: String::from("GlobalQuery_NamespaceList"),
+                error: Some(error.clone()),
+                ..QueryFixture::default()
+            },
+        );
+        let backend = FakeIris::new(fixtures);
+        let (manager, mut output_rx) = setup(&backend).await;
+
+        assert_eq!(manager.globals().await, Err(error));
+        assert_eq!(manager.productions().await.map(|list| list.len()), Ok(2));
+        assert_eq!(backend.connections(), 1);
+        assert_eq!(connected_notifications(&mut output_rx).await, vec![true]);
+    }
+
+    #[tokio::test]
+    async fn settings_changes() {
+        let refresh = |connected: &str| {
+            vec![
+                format!("intersystems/connected {}", connected),
+                String::from("intersystems/refresh"),
+            ]
+        };
+        // New settings, their connections, the token afterwards, the notifications and
+        // the productions that can be listed then
+        let cases = vec![
+            (options("%SYS"), 2, None, refresh("true"), Ok(2)),
+            (options("USER"), 1, Some("token"), Vec::new(), Ok(2)),
+            (
+                Options::default(),
+                1,
+                None,
+                refresh("false"),
+                Err(IrisError::NotConfigured),
+            ),
+        ];
+        for (options, connections, token, expected, productions) in cases {
+            let backend = FakeIris::new(Fixtures {
+                classmethods: vec![(
+                    String::from("%RoutineMgr:ConstructCSPSession"),
+                    String::from("token"),
+                )]
+                .into_iter()
+                .collect(),
+                ..fixtures()
+            });
+            let (manager, mut output_rx) = setup(&backend).await;
+            assert_eq!(manager.token().await, Ok(Some(String::from("token"))));
+            notifications(&mut output_rx).await;
+
+            manager.reparse(&options).await;
+
+            assert_eq!(backend.connections(), connections);
+            assert_eq!(manager.state.lock().await.token.as_deref(), token);
+            assert_eq!(notifications(&mut output_rx).await, expected);
+            assert_eq!(
+                manager.productions().await.map(|list| list.len()),
+                productions
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn atelier_settings_use_web_server_port() {
+        let backend = FakeIris::new(fixtures());
+        let (manager, _output_rx) = setup(&backend).await;
+        let mut options = options("USER");
+        let conn = options
+            .objectscript
+            .as_mut()
+            .unwrap()
+            .conn
+            .as_mut()
+            .unwrap();
+        conn.backend = Some(ConnBackend::Atelier);
+        conn.https = Some(true);
+
+        // Without a web server port there is nothing to connect to
+        manager.reparse(&options).await;
+        assert_eq!(manager.productions().await, Err(IrisError::NotConfigured));
+        assert_eq!(backend.connections(), 1);
+
+        let conn = options
+            .objectscript
+            .as_mut()
+            .unwrap()
+            .conn
+            .as_mut()
+            .unwrap();
+        conn.port = Some(52773);
+        manager.reparse(&options).await;
+        manager.productions().await.unwrap();
+
+        let settings = backend.connection_settings();
+        assert_eq!(settings.len(), 2);
+        assert_eq!(settings[1].backend, ConnBackend::Atelier);
+        assert_eq!(settings[1].port, 52773);
+        assert!(settings[1].https);
+    }
+
+    #[tokio::test]
+    async fn server_definition_replaces_conn() {
+        let mut options = options("USER");
+        let conn = options
+            .objectscript
+            .as_mut()
+            .unwrap()
+            .conn
+            .as_mut()
+            .unwrap();
+        conn.server = Some(String::from("dev"));
+        conn.backend = Some(ConnBackend::Atelier);
+        conn.password = Some(String::from("ignored"));
+        options.intersystems = serde_json::from_value(json!({
+            "servers": {
+                "dev": {
+                    "webServer": {
+                        "scheme": "https",
+                        "host": "iris.example.com",
+                        "port": 443,
+                        "pathPrefix": "iris"
+                    },
+                    "superServer": { "port": 1972 },
+                    "username": "developer"
+                },
+                "/default": "dev"
+            }
+        }))
+        .unwrap();
+
+        let settings = connection_settings(&options).unwrap();
+        assert_eq!(settings.server_name.as_deref(), Some("dev"));
+        assert_eq!(
+            (settings.host.as_str(), settings.port, settings.https),
+            ("iris.example.com", 443, true)
+        );
+        assert_eq!(
+            (settings.super_host.as_str(), settings.super_port),
+            ("iris.example.com", 1972)
+        );
+        assert_eq!(settings.path_prefix, "iris");
+        assert_eq!(settings.username, "developer");
+        assert_eq!(settings.password, "");
+
+        let conn = options
+            .objectscript
+            .as_mut()
+            .unwrap()
+            .conn
+            .as_mut()
+            .unwrap();
+        conn.server = Some(String::from("/default"));
+        assert_eq!(connection_settings(&options), None);
+    }
+
+    #[test]
+    fn tls_only_when_enabled() {
+        let mut options = options("USER");
+        let conn = options
+            .objectscript
+            .as_mut()
+            .unwrap()
+            .conn
+            .as_mut()
+            .unwrap();
+        conn.tls = Some(ConnTls {
+            ca_file: Some(String::from("/etc/iris/ca.pem")),
+            verify_hostname: Some(false),
+            ..ConnTls::default()
+        });
+        assert_eq!(connection_settings(&options).unwrap().tls, None);
+
+        let conn = options
+            .objectscript
+            .as_mut()
+            .unwrap()
+            .conn
+            .as_mut()
+            .unwrap();
+        conn.tls.as_mut().unwrap().enabled = Some(true);
+        assert_eq!(
+            connection_settings(&options).unwrap().tls,
+            Some(TlsSettings {
+                ca_file: Some(PathBuf::from("/etc/iris/ca.pem")),
+                cert_file: None,
+                key_file: None,
+                verify_hostname: false,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn password_is_requested_once() {
+        let backend = FakeIris::new(fixtures());
+        let (client, _output_rx, requests) = client(Some(credentials("SYS")));
+        let manager = ConnectionManager::new(client, Arc::new(backend.clone()), None);
+        manager.reparse(&options("USER")).await;
+
+        backend.break_connection();
+        manager.globals().await.unwrap();
+        manager.reparse(&options("%SYS")).await;
+
+        let passwords: Vec<_> = backend
+            .connection_settings()
+            .into_iter()
+            .map(|settings| settings.password)
+            .collect();
+        assert_eq!(passwords, vec!["SYS", "SYS", "SYS"]);
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(
+            requests[0],
+            json!({ "host": "localhost", "port": 1972, "namespace": "USER", "username": "_SYSTEM" })
+        );
+    }
+
+    #[tokio::test]
+    async fn rejected_password_is_requested_again() {
+        let backend = FakeIris::new(fixtures());
+        let error = IrisError::AuthFailed {
+            message: String::from("Access Denied"),
+        };
+        backend.reject_connections(Some(error.clone()));
+        let (client, _output_rx, requests) = client(Some(credentials("wrong")));
+        let manager = ConnectionManager::new(client, Arc::new(backend.clone()), None);
+        manager.reparse(&options("USER")).await;
+
+        assert_eq!(manager.state.lock().await.credentials, None);
+        manager.reparse(&options("%SYS")).await;
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn state_is_not_locked_while_asking_for_credentials() {
+        let backend = FakeIris::new(fixtures());
+        let (output_tx, mut output_rx) = mpsc::channel::<String>(16);
+        let client = Arc::new(InterSystemsLspClient::new(output_tx));
+        let manager = Arc::new(ConnectionManager::new(
+            Arc::clone(&client),
+            Arc::new(backend.clone()),
+            None,
+        ));
+        let connecting = {
+            let manager = Arc::clone(&manager);
+            tokio::spawn(async move { manager.reparse(&options("USER")).await })
+        };
+
+        let request = loop {
+            let message: Value = serde_json::from_str(&output_rx.next().await.unwrap()).unwrap();
+            if message["method"] == "intersystems/credentials" {
+                break message;
+            }
+        };
+        assert!(manager.state.try_lock().is_some());
+        assert_eq!(backend.connections(), 0);
+
+        let id: Id = serde_json::from_value(request["id"].clone()).unwrap();
+        client
+            .handle(Response::result(json!(credentials("SYS")), id))
+            .await;
+        connecting.await.unwrap();
+        assert_eq!(backend.connections(), 1);
+    }
+
+    #[tokio::test]
+    async fn missing_credentials_fail_authentication() {
+        let backend = FakeIris::new(fixtures());
+        let (client, mut output_rx, _) = client(None);
+        let manager = ConnectionManager::new(client, Arc::new(backend.clone()), None);
+        manager.reparse(&options("USER")).await;
+
+        assert_eq!(
+            manager.productions().await,
+            Err(IrisError::AuthFailed {
+                message: String::from("No credentials were provided")
+            })
+        );
+        assert_eq!(backend.connections(), 0);
+        assert_eq!(connected_notifications(&mut output_rx).await, vec![false]);
+    }
+
+    #[tokio::test]
+    async fn folders_with_own_settings() {
+        let backend = FakeIris::new(fixtures());
+        let (client, _output_rx, _) = client(Some(credentials("SYS")));
+        let connections = WorkspaceConnections::new(client, Arc::new(backend.clone()));
+        let same = Url::parse("file:///same").unwrap();
+        let other = Url::parse("file:///other").unwrap();
+
+        connections
+            .reparse(
+                &options("USER"),
+                vec![
+                    (same.clone(), options("USER")),
+                    (other.clone(), options("SAMPLES")),
+                ],
+            )
+            .await;
+        connections.get(Some(&other)).await.globals().await.unwrap();
+
+        let namespaces: Vec<_> = backend
+            .connection_settings()
+            .into_iter()
+            .map(|settings| settings.ns)
+            .collect();
+        assert_eq!(namespaces, vec!["USER", "SAMPLES"]);
+        assert!(Arc::ptr_eq(
+            &connections.get(Some(&same)).await,
+            &connections.get(None).await
+        ));
+        assert!(!Arc::ptr_eq(
+            &connections.get(Some(&other)).await,
+            &connections.get(None).await
+        ));
+
+        connections.reparse(&options("USER"), Vec::new()).await;
+        assert!(Arc::ptr_eq(
+            &connections.get(Some(&other)).await,
+            &connections.get(None).await
+        ));
+    }
+
+    #[tokio::test]
+    async fn slow_query_does_not_block_executor() {
+        let backend = FakeIris::new(fixtures());
+        let (manager, _output_rx) = setup(&backend).await;
+        let manager = Arc::new(manager);
+        backend.set_latency(Duration::from_millis(500));
+
+        let slow_manager = Arc::clone(&manager);
+        let slow = tokio::spawn(async move { slow_manager.globals().await });
+        let started = Instant::now();
+        tokio::time::delay_for(Duration::from_millis(10)).await;
+        assert!(started.elapsed() < Duration::from_millis(250));
+
+        assert_eq!(
+            slow.await.unwrap(),
+            Ok(vec![Global {
+                name: String::from("^Demo")
+            }])
+        );
+        assert_eq!(backend.connections(), 1);
+    }
+
+    #[tokio::test]
+    async fn production_items_by_superclass() {
+        let backend = FakeIris::new(fixtures());
+        let (manager, _output_rx) = setup(&backend).await;
+
+        manager
+            .production_operations(String::from("Demo.Production"))
+            .await
+            .unwrap();
+
+        let statement = backend.statements().pop().unwrap();
+        assert_eq!(
+            statement.params(),
+            ["Demo.Production", "Ens.BusinessOperation"]
+        );
+    }
+
+    #[tokio::test]
+    async fn hostile_production_ids_are_bound() {
+        let backend = FakeIris::new(fixtures());
+        let (manager, _output_rx) = setup(&backend).await;
+        manager
+            .production_processes(String::from("Demo.Production"))
+            .await
+            .unwrap();
+        let expected_sql = backend.statements().pop().unwrap().sql().to_owned();
+
+        for id in HOSTILE_IDS {
+            manager
+                .production_processes(String::from(*id))
+                .await
+                .unwrap();
+
+            let statement = backend.statements().pop().unwrap();
+            assert_eq!(statement.sql(), expected_sql);
+            assert_eq!(statement.params(), [*id, "Ens.BusinessProcess"]);
+        }
+    }
+
+    #[tokio::test]
+    async fn hostile_production_id_only_matches_itself() {
+        let backend = FakeIris::new(fixtures());
+        let (manager, _output_rx) = setup(&backend).await;
+
+        let services = manager
+            .production_services(String::from(HOSTILE_IDS[0]))
+            .await;
+        assert_eq!(
+            services,
+            Ok(vec![ProductionService {
+                id: String::from("Hostile.Service")
+            }])
+        );
+
+        for id in &HOSTILE_IDS[1..] {
+            assert_eq!(
+                manager.production_services(String::from(*id)).await,
+                Ok(Vec::new())
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn control_productions() {
+        struct Case {
+            action: ProductionAction,
+            production: &'static str,
+            timeout: u32,
+            force: bool,
+            /// The state of `Demo.Production` after the action
+            state: i32,
+            /// What the SQL function returns
+            returns: &'static str,
+            status: Result<&'static str, IrisError>,
+            calls: Vec<[&'static str; 4]>,
+        }
+        let cases = vec![
+            Case {
+                action: ProductionAction::Start,
+                production: "Demo.Production",
+                timeout: 10,
+                force: false,
+                state: 1,
+                returns: "",
+                status: Ok("Running"),
+                calls: vec![["StartProduction", "Demo.Production", "10", "0"]],
+            },
+            Case {
+                action: ProductionAction::Stop,
+                production: "Demo.Production",
+                timeout: 60,
+                force: true,
+                state: 2,
+                returns: "",
+                status: Ok("Stopped"),
+                calls: vec![["StopProduction", "Demo.Production", "60", "1"]],
+            },
+            // Only the current production can be stopped
+            Case {
+                action: ProductionAction::Restart,
+                production: "Other.Production",
+                timeout: 10,
+                force: false,
+                state: 1,
+                returns: "",
+                status: Err(IrisError::Server {
+                    message: String::from(
+                        "Other.Production is not the current production, Demo.Production is",
+                    ),
+                }),
+                calls: Vec::new(),
+            },
+            Case {
+                action: ProductionAction::Update,
+                production: "Demo.Production",
+                timeout: 10,
+                force: false,
+                state: 1,
+                returns: "ERROR #5001: Production is not running",
+                status: Err(IrisError::Server {
+                    message: String::from("ERROR #5001: Production is not running"),
+                }),
+                calls: vec![["UpdateProduction", "Demo.Production", "10", "0"]],
+            },
+        ];
+        for case in cases {
+            let mut fixtures = fixtures();
+            fixtures.production_state.1 = case.state;
+            fixtures.queries.push(QueryFixture {
+                matches: String::from("SELECT IrisLS.ControlProductionV1("),
+                rows: vec![vec![String::from(case.returns)]],
+                ..QueryFixture::default()
+            });
+            let backend = FakeIris::new(fixtures);
+            let (manager, _output_rx) = setup(&backend).await;
+
+            let production = manager
+                .control_production(
+                    case.action,
+                    String::from(case.production),
+                    case.timeout,
+                    case.force,
+                )
+                .await;
+
+            let id = String::from(case.production);
+            let expected = case.status.map(|status| Production {
+                id,
+                status: String::from(status),
+            });
+            assert_eq!(production, expected, "{:?}", case.action);
+            assert_eq!(function_calls(&backend, "ControlProductionV1"), case.calls);
+        }
+    }
+
+    #[tokio::test]
+    async fn changes_are_not_retried_on_a_broken_connection() {
+        let backend = FakeIris::new(fixtures());
+        let (manager, _output_rx) = setup(&backend).await;
+
+        backend.break_connection();
+        let production = manager
+            .control_production(
+                ProductionAction::Stop,
+                String::from("Demo.Production"),
+                10,
+                false,
+            )
+            .await;
+
+        assert_eq!(
+            production,
+            Err(IrisError::network("connection reset by peer"))
+        );
+        assert!(function_calls(&backend, "ControlProductionV1").is_empty());
+        assert_eq!(backend.connections(), 1);
+
+        let production = manager
+            .control_production(
+                ProductionAction::Stop,
+                String::from("Demo.Production"),
+                10,
+                false,
+            )
+            .await;
+        assert!(production.is_ok());
+        assert_eq!(backend.connections(), 2);
+        assert_eq!(function_calls(&backend, "ControlProductionV1").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn production_sources_with_host_classes() {
+        let mut fixtures = fixtures();
+        fixtures.queries.push(QueryFixture {
+            matches: String::from("select distinct ClassName from Ens_Config.Item"),
+            params: Some(vec![String::from("Demo.Production")]),
+            rows: vec![
+                vec![String::from("Demo.Missing")],
+                vec![String::from("Demo.Service")],
+                vec![String::from("EnsLib.File.PassthroughOperation")],
+            ],
+            ..QueryFixture::default()
+        });
+        fixtures.documents = vec![
+            (
+                String::from("Demo.Production.cls"),
+                vec![String::from("Class Demo.Production Extends Ens.Production")],
+            ),
+            (
+                String::from("Demo.Service.cls"),
+                vec![String::from(
+                    "Class Demo.Service Extends Ens.BusinessService",
+                )],
+            ),
+        ]
+        .into_iter()
+        .collect();
+        let backend = FakeIris::new(fixtures);
+        let (manager, _output_rx) = setup(&backend).await;
+
+        let sources = manager
+            .production_sources(String::from("Demo.Production"))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            sources.production.lines,
+            vec!["Class Demo.Production Extends Ens.Production"]
+        );
+        assert_eq!(
+            sources.hosts,
+            vec![ClassSource {
+                name: String::from("Demo.Service"),
+                lines: vec![String::from(
+                    "Class Demo.Service Extends Ens.BusinessService"
+                )],
+            }]
+        );
+        assert_eq!(
+            sources.skipped,
+            vec!["Demo.Missing", "EnsLib.File.PassthroughOperation"]
+        );
+        assert_eq!(
+            manager
+                .production_sources(String::from("Other.Production"))
+                .await,
+            Err(IrisError::Server {
+                message: String::from("Production class Other.Production does not exist")
+            })
+        );
+    }
+
+    fn query(matches: &str, params: &[&str], rows: &[&[&str]]) -> QueryFixture {
+        QueryFixture {
+            matches: String::from(matches),
+            params: Some(params.iter().map(|param| String::from(*param)).collect()),
+            rows: rows
+                .iter()
+                .map(|row| row.iter().map(|column| String::from(*column)).collect())
+                .collect(),
+            ..QueryFixture::default()
+        }
+    }
+
+    fn item_fixtures() -> Fixtures {
+        let mut fixtures = fixtures();
+        fixtures.queries = vec![
+            query(
+                "from Ens_Config.Item where",
+                &["Demo.Production", "Demo.FileOperation"],
+                &[&["7", "Demo.FileOperation", "Files", "1", "2", "Writes files"]],
+            ),
+            query(
+                "from %Dictionary.CompiledParameter",
+                &["Demo.FileOperation", "ADAPTER"],
+                &[&["EnsLib.File.OutboundAdapter"]],
+            ),
+            query(
+                "from %Dictionary.ClassDefinition",
+                &["Demo.FileOperation"],
+                &[&["Ens.BusinessOperation"]],
+            ),
+            query(
+                "from %Dictionary.ClassDefinition",
+                &["Ens.BusinessOperation"],
+                &[&["Ens.Host"]],
+            ),
+            query(
+                "from %Dictionary.ClassDefinition",
+                &["Ens.Host"],
+                &[&["%RegisteredObject"]],
+            ),
+            query(
+                "from %Dictionary.ClassDefinition",
+                &["EnsLib.File.OutboundAdapter"],
+                &[&["Ens.OutboundAdapter,Common"]],
+            ),
+            query(
+                "from %Dictionary.ParameterDefinition",
+                &["Ens.Host", "SETTINGS"],
+                &[&["AlertOnError,RetryInterval"]],
+            ),
+            query(
+                "from %Dictionary.ParameterDefinition",
+                &["Demo.FileOperation", "SETTINGS"],
+                &[&["Archive:Basic,-AlertOnError,Overwrite"]],
+            ),
+            query(
+                "from %Dictionary.ParameterDefinition",
+                &["EnsLib.File.Common", "SETTINGS"],
+                &[&["FilePath:Basic:directorySelector,Charset"]],
+            ),
+            query(
+                "from %Dictionary.ParameterDefinition",
+                &["EnsLib.File.OutboundAdapter", "SETTINGS"],
+                &[&["-Charset"]],
+            ),
+            query(
+                "from %Dictionary.CompiledProperty",
+                &["Demo.FileOperation"],
+                &[
+                    &["Archive", "\"none\""],
+                    &["Overwrite", "0"],
+                    &["RetryInterval", "5"],
+                    &["Other", ""],
+                ],
+            ),
+            query(
+                "from %Dictionary.CompiledProperty",
+                &["EnsLib.File.OutboundAdapter"],
+                &[&["FilePath", "\"C:\\\"\"out\"\"\""]],
+            ),
+            query(
+                "from Ens_Config.Item_Settings",
+                &["7"],
+                &[
+                    &["FilePath", "Adapter", "/data/out"],
+                    &["Overwrite", "Host", "1"],
+                    &["ReplyCodeActions", "Host", "E=R"],
+                ],
+            ),
+        ];
+        fixtures
+    }
+
+    #[tokio::test]
+    async fn production_item_with_settings() {
+        let backend = FakeIris::new(item_fixtures());
+        let (manager, _output_rx) = setup(&backend).await;
+
+        let item = manager
+            .production_item(
+                String::from("Demo.Production"),
+                String::from("Demo.FileOperation"),
+            )
+            .await
+            .unwrap();
+
+        let setting = |name: &str,
+                       target: &str,
+                       value: Option<&str>,
+                       default_value: Option<&str>| ProductionItemSetting {
+            name: String::from(name),
+            target: String::from(target),
+            value: value.map(String::from),
+            default_value: default_value.map(String::from),
+        };
+        assert_eq!(
+            item,
+            ProductionItem {
+                name: String::from("Demo.FileOperation"),
+                class_name: String::from("Demo.FileOperation"),
+                category: String::from("Files"),
+                enabled: true,
+                pool_size: Some(2),
+                adapter_class: Some(String::from("EnsLib.File.OutboundAdapter")),
+                comment: String::from("Writes files"),
+                settings: vec![
+                    setting("RetryInterval", "Host", None, Some("5")),
+                    setting("Archive", "Host", None, Some("none")),
+                    setting("Overwrite", "Host", Some("1"), Some("0")),
+                    setting(
+                        "FilePath",
+                        "Adapter",
+                        Some("/data/out"),
+                        Some("C:\\\"out\"")
+                    ),
+                    setting("ReplyCodeActions", "Host", Some("E=R"), None),
+                ],
+            }
+        );
+        assert_eq!(
+            manager
+                .production_item(
+                    String::from("Demo.Production"),
+                    String::from("Demo.Missing")
+                )
+                .await,
+            Err(IrisError::Server {
+                message: String::from("Production Demo.Production has no item Demo.Missing")
+            })
+        );
+    }
+
+    /// The arguments of the calls that changed items of the production, without the
+    /// production name.
+    fn changes(backend: &FakeIris) -> Vec<Vec<String>> {
+        function_calls(backend, "ChangeItemV1")
+            .into_iter()
+            .map(|mut args| args.split_off(1))
+            .collect()
+    }
+
+    fn setting_change(target: &str, name: &str, value: Option<&str>) -> Vec<String> {
+        let remove = if value.is_some() { "0" } else { "1" };
+        let args = [
+            "Demo.FileOperation",
+            "",
+            target,
+            name,
+            value.unwrap_or_default(),
+            remove,
+            "10",
+            "0",
+        ];
+        args.iter().map(|arg| String::from(*arg)).collect()
+    }
+
+    #[tokio::test]
+    async fn disable_item_and_update_production() {
+        let backend = FakeIris::new(item_fixtures());
+        let (manager, _output_rx) = setup(&backend).await;
+
+        let item = manager
+            .enable_production_item(
+                String::from("Demo.Production"),
+                String::from("Demo.FileOperation"),
+                false,
+                30,
+                true,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(item.name, "Demo.FileOperation");
+        assert_eq!(
+            changes(&backend),
+            [["Demo.FileOperation", "0", "", "", "", "0", "30", "1"]]
+        );
+    }
+
+    #[tokio::test]
+    async fn items_change_only_in_running_production() {
+        let mut fixtures = item_fixtures();
+        fixtures.production_state.1 = 2;
+        let backend = FakeIris::new(fixtures);
+        let (manager, _output_rx) = setup(&backend).await;
+
+        assert_eq!(
+            manager
+                .enable_production_item(
+                    String::from("Demo.Production"),
+                    String::from("Demo.FileOperation"),
+                    true,
+                    10,
+                    false
+                )
+                .await,
+            Err(IrisError::Server {
+                message: String::from("Demo.Production is not running")
+            })
+        );
+        assert_eq!(changes(&backend), Vec::<Vec<String>>::new());
+    }
+
+    #[tokio::test]
+    async fn set_item_settings() {
+        let backend = FakeIris::new(item_fixtures());
+        let (manager, _output_rx) = setup(&backend).await;
+        let set = |target: Option<&str>, setting: &str, value: Option<&str>| {
+            manager.set_production_item_setting(
+                String::from("Demo.Production"),
+                String::from("Demo.FileOperation"),
+                target.map(String::from),
+                String::from(setting),
+                value.map(String::from),
+                10,
+                false,
+            )
+        };
+
+        set(None, "Overwrite", Some("0")).await.unwrap();
+        set(None, "Archive", Some("all")).await.unwrap();
+        set(Some("Adapter"), "FilePath", None).await.unwrap();
+        set(None, "Archive", None).await.unwrap();
+
+        assert_eq!(
+            changes(&backend),
+            vec![
+                setting_change("Host", "Overwrite", Some("0")),
+                setting_change("Host", "Archive", Some("all")),
+                setting_change("Adapter", "FilePath", None),
+                setting_change("Host", "Archive", None),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_setting_is_rejected() {
+        let backend = FakeIris::new(item_fixtures());
+        let (manager, _output_rx) = setup(&backend).await;
+        let set = |target: Option<&str>, setting: &str| {
+            manager.set_production_item_setting(
+                String::from("Demo.Production"),
+                String::from("Demo.FileOperation"),
+                target.map(String::from),
+                String::from(setting),
+                Some(String::from("1")),
+                10,
+                false,
+            )
+        };
+
+        let unknown = |setting: &str| IrisError::UnknownSetting {
+            item: String::from("Demo.FileOperation"),
+            setting: String::from(setting),
+            known: ["RetryInterval", "Archive", "Overwrite", "FilePath"]
+                .iter()
+                .map(|name| String::from(*name))
+                .collect(),
+        };
+        assert_eq!(
+            set(None, "ReplyCodeActions").await,
+            Err(unknown("ReplyCodeActions"))
+        );
+        assert_eq!(
+            set(None, "AlertOnError").await,
+            Err(unknown("AlertOnError"))
+        );
+        assert_eq!(
+            set(Some("Host"), "FilePath").await,
+            Err(unknown("FilePath"))
+        );
+        assert_eq!(set(None, "Charset").await, Err(unknown("Charset")));
+        assert_eq!(changes(&backend), Vec::<Vec<String>>::new());
+    }
+
+    #[tokio::test]
+    async fn production_status_changes_are_notified() {
+        let mut fixtures = fixtures();
+        fixtures.queries.push(query(
+            "Statistics_EnumerateHostStatus",
+            &[],
+            &[&["Demo.Service", "OK"]],
+        ));
+        let backend = FakeIris::new(fixtures);
+        let (manager, mut output_rx) = setup(&backend).await;
+        let manager = Arc::new(manager);
+        notifications(&mut output_rx).await;
+
+        manager
+            .watch_productions(Some(Duration::from_millis(20)))
+            .await;
+        assert_eq!(notifications(&mut output_rx).await, Vec::<String>::new());
+
+        backend.update_fixtures(|fixtures| {
+            fixtures.production_state.1 = 4;
+            fixtures.queries.last_mut().unwrap().rows =
+                vec![vec![String::from("Demo.Service"), String::from("Error")]];
+        });
+        let json = tokio::time::timeout(Duration::from_secs(1), output_rx.next())
+            .await
+            .unwrap()
+            .unwrap();
+        let message: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(message["method"], "intersystems/productionStatusChanged");
+        assert_eq!(
+            serde_json::from_value::<InterSystemsProductionStatusChangedParams>(
+                message["params"].clone()
+            )
+            .unwrap(),
+            InterSystemsProductionStatusChangedParams {
+                scope_uri: None,
+                production: Some(Production {
+                    id: String::from("Demo.Production"),
+                    status: String::from("Troubled")
+                }),
+                hosts: vec![ProductionHostStatus {
+                    name: String::from("Demo.Service"),
+                    status: String::from("Error")
+                }],
+            }
+        );
+        assert_eq!(notifications(&mut output_rx).await, Vec::<String>::new());
+
+        manager.watch_productions(None).await;
+        backend.update_fixtures(|fixtures| fixtures.production_state.1 = 2);
+        assert_eq!(notifications(&mut output_rx).await, Vec::<String>::new());
+    }
+
+    #[tokio::test]
+    async fn production_status_is_not_checked_without_production_state() {
+        let backend = FakeIris::new(Fixtures {
+            production_state_unknown: true,
+            ..fixtures()
+        });
+        let (manager, mut output_rx) = setup(&backend).await;
+        let manager = Arc::new(manager);
+        notifications(&mut output_rx).await;
+
+        manager
+            .watch_productions(Some(Duration::from_millis(20)))
+            .await;
+        time::delay_for(Duration::from_millis(100)).await;
+
+        // The checks have stopped, so a state that turns up later goes unnoticed
+        backend.update_fixtures(|fixtures| fixtures.production_state_unknown = false);
+        time::delay_for(Duration::from_millis(100)).await;
+        backend.update_fixtures(|fixtures| fixtures.production_state.1 = 2);
+        assert_eq!(notifications(&mut output_rx).await, Vec::<String>::new());
+    }
+
+    #[tokio::test]
+    async fn production_status_is_not_checked_while_disconnected() {
+        let backend = FakeIris::new(fixtures());
+        let (client, _output_rx, requests) = client(None);
+        let manager = Arc::new(ConnectionManager::new(
+            client,
+            Arc::new(backend.clone()),
+            None,
+        ));
+        manager.reparse(&options("USER")).await;
+        assert_eq!(requests.lock().unwrap().len(), 1);
+
+        manager
+            .watch_productions(Some(Duration::from_millis(10)))
+            .await;
+        tokio::time::delay_for(Duration::from_millis(100)).await;
+
+        assert_eq!(requests.lock().unwrap().len(), 1);
+        assert_eq!(backend.connections(), 0);
+    }
+
+    #[test]
+    fn poll_interval_from_options() {
+        let mut options = options("USER");
+        assert_eq!(poll_interval(&options), None);
+        for (seconds, interval) in [(0, None), (5, Some(Duration::from_secs(5)))] {
+            options.intersystems = Some(Intersystems {
+                productions: Some(Productions {
+                    poll_interval: Some(seconds),
+                }),
+                ..Intersystems::default()
+            });
+            assert_eq!(poll_interval(&options), interval);
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_until_limit() {
+        assert_eq!(backoff(1), Duration::from_secs(1));
+        assert_eq!(backoff(2), Duration::from_secs(2));
+        assert_eq!(backoff(4), Duration::from_secs(8));
+        assert_eq!(backoff(100), MAX_BACKOFF);
+    }
 }
@@ -0,0 +1,221 @@
+use crate::connection::{ClassSource, ProductionSources};
+use serde::{Deserialize, Serialize};
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+use tokio::fs;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ExportedKind {
+    /// A class in UDL
+    Class,
+    /// The `ProductionDefinition` XData of the production class as XML
+    ProductionDefinition,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportedFile {
+    pub document: String,
+    pub kind: ExportedKind,
+    /// Relative to the manifest, with `/` as separator
+    pub path: String,
+}
+
+/// Records what an export wrote. It has no timestamp, so exporting an unchanged
+/// production leaves the working tree clean.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportManifest {
+    pub production: String,
+    pub files: Vec<ExportedFile>,
+    /// Host classes that were not exported
+    pub skipped: Vec<String>,
+}
+
+/// Writes the classes as UDL into `<Package>/<Name>.cls` below `directory`, the layout
+/// VSCode-ObjectScript uses, and the `ProductionDefinition` XData as XML next to the
+/// production class. The manifest goes to `<production>.export.json` in `directory`.
+///
+/// Returns the path of the manifest and of every file written.
+pub async fn write_production(
+    directory: &Path,
+    sources: &ProductionSources,
+) -> io::Result<(PathBuf, Vec<PathBuf>)> {
+    let production = &sources.production;
+    let mut files = vec![class_file(production)];
+    if let Some(definition) = production_definition(&production.lines) {
+        files.push((
+            ExportedFile {
+                document: format!("{}.cls", production.name),
+                kind: ExportedKind::ProductionDefinition,
+                path: format!("{}.ProductionDefinition.xml", class_stem(&production.name)),
+            },
+            definition,
+        ));
+    }
+    files.extend(sources.hosts.iter().map(class_file));
+
+    let mut paths = Vec::new();
+    for (file, content) in &files {
+        let path = directory.join(&file.path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::write(&path, content).await?;
+        paths.push(path);
+    }
+
+    let manifest = ExportManifest {
+        production: production.name.clone(),
+        files: files.into_iter().map(|(file, _)| file).collect(),
+        skipped: sources.skipped.clone(),
+    };
+    let manifest_path = directory.join(format!("{}.export.json", production.name));
+    let mut json = serde_json::to_string_pretty(&manifest)?;
+    json.push('\n');
+    fs::write(&manifest_path, json).await?;
+    Ok((manifest_path, paths))
+}
+
+fn class_file(class: &ClassSource) -> (ExportedFile, String) {
+    let file = ExportedFile {
+        document: format!("{}.cls", class.name),
+        kind: ExportedKind::Class,
+        path: format!("{}.cls", class_stem(&class.name)),
+    };
+    (file, join_lines(&class.lines))
+}
+
+/// `Demo.Production` becomes `Demo/Production`.
+fn class_stem(class: &str) -> String {
+    class.replace('.', "/")
+}
+
+fn join_lines(lines: &[String]) -> String {
+    let mut content = lines.join("\n");
+    content.push('\n');
+    content
+}
+
+/// The XML in the `XData ProductionDefinition` block of a production class in UDL.
+pub fn production_definition(lines: &[String]) -> Option<String> {
+    let start = lines.iter().position(|line| {
+        line.strip_prefix("XData ProductionDefinition")
+            .is_some_and(|rest| {
+                rest.is_empty() || rest.starts_with(|c: char| c.is_whitespace() || c == '[')
+            })
+    })?;
+    let mut body = lines[start + 1..]
+        .iter()
+        .skip_while(|line| line.trim().is_empty())
+        .map(|line| line.trim_end());
+    if body.next()? != "{" {
+        return None;
+    }
+    let xml: Vec<_> = body
+        .take_while(|line| *line != "}")
+        .map(str::to_owned)
+        .collect();
+    Some(join_lines(&xml))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(str::to_owned).collect()
+    }
+
+    fn production_class() -> Vec<String> {
+        lines(
+            "Class Demo.Production Extends Ens.Production\n\
+             {\n\
+             \n\
+             XData ProductionDefinition [ XMLNamespace = \"http://www.intersystems.com/production\" ]\n\
+             {\n\
+             <Production Name=\"Demo.Production\">\n\
+             \x20 <Item Name=\"Demo.Service\" ClassName=\"Demo.Service\" Enabled=\"true\">\n\
+             \x20 </Item>\n\
+             </Production>\n\
+             }\n\
+             \n\
+             }",
+        )
+    }
+
+    #[test]
+    fn definition_from_xdata() {
+        assert_eq!(
+            production_definition(&production_class()).as_deref(),
+            Some(
+                "<Production Name=\"Demo.Production\">\n  \
+                 <Item Name=\"Demo.Service\" ClassName=\"Demo.Service\" Enabled=\"true\">\n  \
+                 </Item>\n</Production>\n"
+            )
+        );
+        assert_eq!(
+            production_definition(&lines("Class Demo.Production Extends Ens.Production\n{\n}")),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn write_classes_definition_and_manifest() {
+        let directory = std::env::temp_dir().join(format!("iris-ls-export-{}", Uuid::new_v4()));
+        let sources = ProductionSources {
+            production: ClassSource {
+                name: String::from("Demo.Production"),
+                lines: production_class(),
+            },
+            hosts: vec![ClassSource {
+                name: String::from("Demo.Service"),
+                lines: lines("Class Demo.Service Extends Ens.BusinessService\n{\n}"),
+            }],
+            skipped: vec![String::from("EnsLib.File.PassthroughOperation")],
+        };
+
+        let (manifest_path, paths) = write_production(&directory, &sources).await.unwrap();
+
+        assert_eq!(
+            paths,
+            vec![
+                directory.join("Demo/Production.cls"),
+                directory.join("Demo/Production.ProductionDefinition.xml"),
+                directory.join("Demo/Service.cls"),
+            ]
+        );
+        let service = std::fs::read_to_string(directory.join("Demo/Service.cls")).unwrap();
+        assert_eq!(
+            service,
+            "Class Demo.Service Extends Ens.BusinessService\n{\n}\n"
+        );
+
+        assert_eq!(manifest_path, directory.join("Demo.Production.export.json"));
+        let manifest: ExportManifest =
+            serde_json::from_str(&std::fs::read_to_string(&manifest_path).unwrap()).unwrap();
+        assert_eq!(manifest.production, "Demo.Production");
+        assert_eq!(
+            manifest
+                .files
+                .iter()
+                .map(|file| (file.kind, file.path.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (ExportedKind::Class, "Demo/Production.cls"),
+                (
+                    ExportedKind::ProductionDefinition,
+                    "Demo/Production.ProductionDefinition.xml"
+                ),
+                (ExportedKind::Class, "Demo/Service.cls"),
+            ]
+        );
+        assert_eq!(manifest.skipped, vec!["EnsLib.File.PassthroughOperation"]);
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -6,9 +6,12 @@ cfg_if::cfg_if! {
         mod config;
 
         pub mod server;
+        pub mod trace;
     }
 }
 
+pub mod backend;
 pub mod protocol;
 pub mod workspace;
 pub mod connection;
+pub mod export;
@@ -1,13 +1,27 @@
 use futures::{channel::mpsc, prelude::*};
 use iris_ls::{
+    backend::{FakeIris, Fixtures},
     protocol::{InterSystemsLspClient, LspCodec},
     server::InterSystemsLspServer,
+    trace::{self, Direction, ReplayOutcome, ReplayResult, SessionRecorder, TraceRecorder},
 };
 use jsonrpc::MessageHandler;
-use log::{trace, LevelFilter};
+use log::{error, info, trace, LevelFilter};
 use std::path::PathBuf;
-use std::{env, error, fs::OpenOptions, sync::Arc};
+use std::{
+    env, error,
+    fs::{File, OpenOptions},
+    io::BufReader,
+    net::Ipv4Addr,
+    process,
+    sync::Arc,
+    time::Duration,
+};
 use structopt::StructOpt;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+};
 use tokio_util::codec::{FramedRead, FramedWrite};
 
 #[derive(Debug, StructOpt)]
@@ -23,44 +37,210 @@ struct Opts {
     /// Write the logging output to FILE
     #[structopt(long, name = "FILE", parse(from_os_str))]
     log_file: Option<PathBuf>,
+
+    /// Accept client connections on the TCP PORT instead of using stdio
+    #[structopt(long, name = "PORT")]
+    listen: Option<u16>,
+
+    /// Accept client connections on the Unix domain SOCKET instead of using stdio
+    #[cfg(unix)]
+    #[structopt(long, name = "SOCKET", parse(from_os_str), conflicts_with = "PORT")]
+    socket: Option<PathBuf>,
+
+    /// Maximum number of requests that are processed at the same time
+    #[structopt(long, name = "N", default_value = "8")]
+    max_concurrent_requests: usize,
+
+    /// Record every message exchanged with the client to TRACE_FILE
+    #[structopt(long, name = "TRACE_FILE", parse(from_os_str))]
+    trace_file: Option<PathBuf>,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Replay a session recorded with --trace-file and compare the responses
+    Replay {
+        /// The recorded session
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+
+        /// Seconds to wait for a response
+        #[structopt(long, default_value = "10")]
+        timeout: u64,
+
+        /// JSON file with the canned IRIS answers for the fake backend
+        #[structopt(long, parse(from_os_str))]
+        fixtures: Option<PathBuf>,
+    },
+}
+
+struct Session {
+    max_concurrent_requests: usize,
+    tracer: Option<Arc<SessionRecorder>>,
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn error::Error>> {
     let opts = Opts::from_args();
-    setup_logger(opts);
+    setup_logger(&opts);
     trace!("Starting server\n");
 
-    let mut stdin = FramedRead::new(tokio::io::stdin(), LspCodec);
-    let (stdout_tx, mut stdout_rx) = mpsc::channel(0);
+    if let Some(Command::Replay {
+        file,
+        timeout,
+        fixtures,
+    }) = &opts.command
+    {
+        let entries = trace::read_trace(BufReader::new(File::open(file)?))?;
+        let fixtures: Fixtures = match fixtures {
+            Some(fixtures) => serde_json::from_reader(BufReader::new(File::open(fixtures)?))?,
+            None => Fixtures::default(),
+        };
+        let sessions = trace::sessions(entries);
+        let mut results = Vec::new();
+        for (session, entries) in sessions.iter().cloned() {
+            if sessions.len() > 1 {
+                println!("session {}", session);
+            }
+            let backend = Arc::new(FakeIris::new(fixtures.clone()));
+            let session_results =
+                trace::replay(entries, backend, Duration::from_secs(*timeout)).await;
+            print_replay(&session_results);
+            results.extend(session_results);
+        }
+        let failures = results
+            .iter()
+            .filter(|result| result.outcome != ReplayOutcome::Match)
+            .count();
+        println!("{} responses, {} differences", results.len(), failures);
+        process::exit(if failures == 0 { 0 } else { 1 });
+    }
+
+    let recorder = match &opts.trace_file {
+        Some(trace_file) => Some(TraceRecorder::new(File::create(trace_file)?).0),
+        None => None,
+    };
+    let max_concurrent_requests = opts.max_concurrent_requests;
+    let session = || Session {
+        max_concurrent_requests,
+        tracer: recorder
+            .as_ref()
+            .map(|recorder| Arc::new(recorder.session())),
+    };
 
-    let client = Arc::new(InterSystemsLspClient::new(stdout_tx.clone()));
+    if let Some(port) = opts.listen {
+        let mut listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port)).await?;
+        info!("Listening on {}", listener.local_addr()?);
+        loop {
+            let (stream, address) = listener.accept().await?;
+            trace!("Accepted connection from {}", address);
+            let (input, output) = tokio::io::split(stream);
+            tokio::spawn(serve(input, output, session()));
+        }
+    }
+
+    #[cfg(unix)]
+    {
+        if let Some(path) = opts.socket {
+            let mut listener = tokio::net::UnixListener::bind(&path)?;
+            info!("Listening on {}", path.display());
+            loop {
+                let (stream, _) = listener.accept().await?;
+                trace!("Accepted connection on {}", path.display());
+                let (input, output) = tokio::io::split(stream);
+                tokio::spawn(serve(input, output, session()));
+            }
+        }
+    }
+
+    let exit_code = serve(tokio::io::stdin(), tokio::io::stdout(), session()).await;
+    if let Some(recorder) = &recorder {
+        recorder.flush();
+    }
+    match exit_code {
+        Some(exit_code) => process::exit(exit_code),
+        None => Ok(()),
+    }
+}
+
+fn print_replay(results: &[ReplayResult]) {
+    for result in results {
+        match &result.outcome {
+            ReplayOutcome::Match => println!("ok       {:?} {}", result.id, result.method),
+            ReplayOutcome::Mismatch { expected, actual } => {
+                println!("mismatch {:?} {}", result.id, result.method);
+                println!("  - {}", expected);
+                println!("  + {}", actual);
+            }
+            ReplayOutcome::Missing { expected } => {
+                println!("missing  {:?} {}", result.id, result.method);
+                println!("  - {}", expected);
+            }
+        }
+    }
+}
+
+/// Runs a language server session over the given streams until the input is closed
+/// or the client sends `exit`. Returns the exit code requested by the client.
+async fn serve<I, O>(input: I, output: O, session: Session) -> Option<i32>
+where
+    I: AsyncRead + Unpin,
+    O: AsyncWrite + Unpin + Send + 'static,
+{
+    let mut input = FramedRead::new(input, LspCodec);
+    let (output_tx, mut output_rx) = mpsc::channel(0);
+
+    let client = Arc::new(InterSystemsLspClient::new(output_tx.clone()));
     let server = Arc::new(InterSystemsLspServer::new(
         Arc::clone(&client),
         Arc::new(env::current_dir().expect("failed to get working directory")),
     ));
-    let mut handler = MessageHandler {
-        server,
+    let mut handler = MessageHandler::with_max_concurrent_requests(
+        Arc::clone(&server),
         client,
-        output: stdout_tx,
-    };
+        output_tx,
+        session.max_concurrent_requests,
+    );
 
+    let tracer = session.tracer.clone();
     tokio::spawn(async move {
-        let mut stdout = FramedWrite::new(tokio::io::stdout(), LspCodec);
-        loop {
-            let message = stdout_rx.next().await.unwrap();
-            stdout.send(message).await.unwrap();
+        let mut output = FramedWrite::new(output, LspCodec);
+        while let Some(message) = output_rx.next().await {
+            if let Some(tracer) = &tracer {
+                tracer.record(Direction::Outgoing, &message);
+            }
+            if let Err(why) = output.send(message).await {
+                error!("Failed to write message: {}", why);
+                break;
+            }
         }
     });
 
-    while let Some(json) = stdin.next().await {
-        handler.handle(&json.unwrap()).await;
-    }
+    while let Some(json) = input.next().await {
+        match json {
+            Ok(json) => {
+                if let Some(tracer) = &session.tracer {
+                    tracer.record(Direction::Incoming, &json);
+                }
+                handler.handle(&json).await
+            }
+            Err(why) => {
+                error!("Failed to read message: {}", why);
+                break;
+            }
+        }
 
-    Ok(())
+        if let Some(exit_code) = server.exit_code() {
+            return Some(exit_code);
+        }
+    }
+    None
 }
 
-fn setup_logger(opts: Opts) {
+fn setup_logger(opts: &Opts) {
     let verbosity_level = if !opts.quiet {
         match opts.verbosity {
             0 => LevelFilter::Error,
@@ -79,7 +259,7 @@ fn setup_logger(opts: Opts) {
         .filter(|metadata| metadata.target() == "jsonrpc" || metadata.target().contains("iris-ls"))
         .chain(std::io::stderr());
 
-    let logger = match opts.log_file {
+    let logger = match &opts.log_file {
         Some(log_file) => logger.chain(
             OpenOptions::new()
                 .write(true)
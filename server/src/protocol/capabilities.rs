@@ -4,6 +4,8 @@ pub trait ClientCapabilitiesExt {
 	fn has_pull_configuration_support(&self) -> bool;
 
 	fn has_push_configuration_support(&self) -> bool;
+
+	fn has_work_done_progress_support(&self) -> bool;
 }
 
 impl ClientCapabilitiesExt for ClientCapabilities {
@@ -19,4 +21,7 @@ impl ClientCapabilitiesExt for ClientCapabilities {
 			.and_then(|cap| cap.dynamic_registration)
 			== Some(true)
 	}
+	fn has_work_done_progress_support(&self) -> bool {
+		self.window.as_ref().and_then(|cap| cap.work_done_progress) == Some(true)
+	}
 }
@@ -1,11 +1,14 @@
 use jsonrpc::client::Result;
 use jsonrpc_derive::{jsonrpc_client, jsonrpc_method};
 use lsp_types::*;
-use super::InterSystemsConnectedParams;
+use super::{
+    InterSystemsConnectedParams, InterSystemsCredentials, InterSystemsCredentialsParams,
+    InterSystemsProductionStatusChangedParams, InterSystemsRefreshParams,
+};
 
 #[jsonrpc_client(InterSystemsLspClient)]
 pub trait LspClient {
-    #[jsonrpc_method("workspace/configuration", kind = "request")]
+    #[jsonrpc_method("workspace/configuration", kind = "request", timeout = 10000)]
     async fn configuration(&self, params: ConfigurationParams) -> Result<serde_json::Value>;
 
     #[jsonrpc_method("window/showMessage", kind = "notification")]
@@ -17,7 +20,7 @@ pub trait LspClient {
     #[jsonrpc_method("$/progress", kind = "notification")]
     async fn progress(&self, params: ProgressParams);
 
-    #[jsonrpc_method("window/workDoneProgress/create", kind = "request")]
+    #[jsonrpc_method("window/workDoneProgress/create", kind = "request", timeout = 10000)]
     async fn work_done_progress_create(&self, params: WorkDoneProgressCreateParams) -> Result<()>;
 
     #[jsonrpc_method("window/logMessage", kind = "notification")]
@@ -25,4 +28,17 @@ pub trait LspClient {
 
     #[jsonrpc_method("intersystems/connected", kind = "notification")]
     async fn connected(&self, params: InterSystemsConnectedParams);
+
+    #[jsonrpc_method("intersystems/refresh", kind = "notification")]
+    async fn refresh(&self, params: InterSystemsRefreshParams);
+
+    #[jsonrpc_method("intersystems/productionStatusChanged", kind = "notification")]
+    async fn production_status_changed(&self, params: InterSystemsProductionStatusChangedParams);
+
+    // The user types the credentials into prompts, which takes a while
+    #[jsonrpc_method("intersystems/credentials", kind = "request", timeout = 600000)]
+    async fn credentials(
+        &self,
+        params: InterSystemsCredentialsParams,
+    ) -> Result<Option<InterSystemsCredentials>>;
 }
@@ -1,6 +1,6 @@
-use bytes::{BufMut, BytesMut};
-use log::trace;
-use std::io::{Error, ErrorKind};
+use bytes::{Buf, BufMut, BytesMut};
+use log::{trace, warn};
+use std::io::Error;
 use tokio_util::codec::{Decoder, Encoder};
 
 pub struct LspCodec;
@@ -10,26 +10,26 @@ impl Decoder for LspCodec {
     type Error = Error;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        if src.len() == 0 {
-            Ok(None)
-        } else {
+        while !src.is_empty() {
             match parser::parse(src) {
                 Ok((remaining, content)) => {
                     trace!("Received message:\n{}\n", content);
-    
+
                     let offset = src.len() - remaining.len();
-                    let _ = src.split_to(offset);
-                    Ok(Some(content))
+                    src.advance(offset);
+                    return Ok(Some(content));
                 }
-                Err(error) => {
-                    if error.is_incomplete() {
-                        Ok(None)
-                    } else {
-                        Err(ErrorKind::InvalidData.into())
+                Err(error) if error.is_incomplete() => return Ok(None),
+                Err(_) => match parser::resync(src) {
+                    Some(offset) => {
+                        warn!("Skipping {} bytes of malformed input", offset);
+                        src.advance(offset);
                     }
-                }
+                    None => return Ok(None),
+                },
             }
         }
+        Ok(None)
     }
 }
 
@@ -48,38 +48,75 @@ impl Encoder<String> for LspCodec {
 
 mod parser {
     use nom::{
-        bytes::streaming::{tag, take, take_while},
-        character::{is_digit, streaming::line_ending},
-        combinator::{map_res, opt},
+        bytes::streaming::{take, take_till, take_while1},
+        character::streaming::{char, line_ending, space0},
+        combinator::{map_res, verify},
+        error::{Error, ErrorKind},
+        multi::many_till,
+        sequence::{delimited, separated_pair, terminated},
         IResult,
     };
     use std::str;
 
+    const CONTENT_LENGTH: &[u8] = b"content-length";
+
     pub fn parse(input: &[u8]) -> IResult<&[u8], String> {
-        let (input, _a) = opt(content_type)(input)?;
-        let (input, length) = content_length(input)?;
-        let (input, _a) = opt(content_type)(input)?;
-        let (input, _a) = line_ending(input)?;
+        let (input, (headers, _)) = many_till(header, line_ending)(input)?;
+        let length = headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("Content-Length"))
+            .and_then(|(_, value)| value.parse::<usize>().ok())
+            .ok_or_else(|| nom::Err::Error(Error::new(input, ErrorKind::Verify)))?;
+
         let (input, content) = map_res(take(length), str::from_utf8)(input)?;
         Ok((input, content.to_owned()))
     }
 
-    fn content_type(input: &[u8]) -> IResult<&[u8], &[u8]> {
-        let (input, _) = tag("Content-Type: application/vscode-jsonrpc;charset=utf")(input)?;
-        let (input, _) = opt(tag("-"))(input)?;
-        let (input, _) = tag("8")(input)?;
-        line_ending(input)
+    /// Returns the offset of the next `Content-Length` header after a malformed frame,
+    /// or `None` if more input is needed to find it.
+    pub fn resync(input: &[u8]) -> Option<usize> {
+        let position = input
+            .windows(CONTENT_LENGTH.len())
+            .skip(1)
+            .position(|window| window.eq_ignore_ascii_case(CONTENT_LENGTH))
+            .map(|position| position + 1);
+
+        // Keep a tail that might be the beginning of a header split across two reads
+        position.or_else(|| {
+            let offset = input.len().saturating_sub(CONTENT_LENGTH.len() - 1);
+            if offset > 0 {
+                Some(offset)
+            } else {
+                None
+            }
+        })
     }
 
-    fn content_length(input: &[u8]) -> IResult<&[u8], usize> {
-        let (input, _) = tag("Content-Length: ")(input)?;
-        let (input, length) = map_res(take_while(is_digit), from_bytes)(input)?;
-        let (input, _) = line_ending(input)?;
-        Ok((input, length))
+    fn header(input: &[u8]) -> IResult<&[u8], (&str, &str)> {
+        terminated(
+            separated_pair(
+                delimited(space0, header_name, space0),
+                char(':'),
+                header_value,
+            ),
+            line_ending,
+        )(input)
     }
 
-    fn from_bytes(input: &[u8]) -> Result<usize, std::num::ParseIntError> {
-        usize::from_str_radix(str::from_utf8(input).unwrap(), 10)
+    fn header_name(input: &[u8]) -> IResult<&[u8], &str> {
+        map_res(take_while1(is_token), str::from_utf8)(input)
+    }
+
+    fn header_value(input: &[u8]) -> IResult<&[u8], &str> {
+        let value = take_till(|c| c == b'\r' || c == b'\n');
+        let (input, value) = verify(map_res(value, str::from_utf8), |value: &str| {
+            !value.contains(char::is_control)
+        })(input)?;
+        Ok((input, value.trim()))
+    }
+
+    fn is_token(c: u8) -> bool {
+        c.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&c)
     }
 
     #[cfg(test)]
@@ -87,22 +124,24 @@ mod parser {
         use super::*;
 
         #[test]
-        fn parse_content_type() {
-            let result =
-                content_type(b"Content-Type: application/vscode-jsonrpc;charset=utf-8\r\n");
-            assert!(result.is_ok());
+        fn parse_header_content_type() {
+            let result = header(b"Content-Type: application/vscode-jsonrpc;charset=utf-8\r\n");
+            assert_eq!(
+                result.unwrap().1,
+                ("Content-Type", "application/vscode-jsonrpc;charset=utf-8")
+            );
         }
 
         #[test]
-        fn parse_content_type_utf8() {
-            let result = content_type(b"Content-Type: application/vscode-jsonrpc;charset=utf8\r\n");
-            assert!(result.is_ok());
+        fn parse_header_whitespace() {
+            let result = header(b"Content-Length :   42 \r\n");
+            assert_eq!(result.unwrap().1, ("Content-Length", "42"));
         }
 
         #[test]
-        fn parse_content_length() {
-            let result = content_length(b"Content-Length: 42\r\n");
-            assert_eq!(result.unwrap().1, 42usize);
+        fn parse_header_content_length() {
+            let result = header(b"Content-Length: 42\r\n");
+            assert_eq!(result.unwrap().1, ("Content-Length", "42"));
         }
 
         #[test]
@@ -127,6 +166,18 @@ mod parser {
             assert_eq!(result.unwrap().1, "{}");
         }
 
+        #[test]
+        fn parse_message_case_insensitive() {
+            let result = parse(b"content-length: 2\r\nCONTENT-TYPE: application/json\r\n\r\n{}");
+            assert_eq!(result.unwrap().1, "{}");
+        }
+
+        #[test]
+        fn parse_message_unknown_headers() {
+            let result = parse(b"X-Foo: bar\nContent-Length: 2\nX-Baz: qux\n\n{}");
+            assert_eq!(result.unwrap().1, "{}");
+        }
+
         #[test]
         fn parse_message_incomplete() {
             let result = parse(b"Content-Length:");
@@ -135,7 +186,19 @@ mod parser {
 
         #[test]
         fn parse_message_invalid() {
-            let error = parse(b"foo").unwrap_err();
+            let error = parse(b"foo bar\r\n").unwrap_err();
+            assert!(!error.is_incomplete());
+        }
+
+        #[test]
+        fn parse_message_without_length() {
+            let error = parse(b"Content-Type: application/json\r\n\r\n{}").unwrap_err();
+            assert!(!error.is_incomplete());
+        }
+
+        #[test]
+        fn parse_message_invalid_length() {
+            let error = parse(b"Content-Length: two\r\n\r\n{}").unwrap_err();
             assert!(!error.is_incomplete());
         }
 
@@ -144,5 +207,50 @@ mod parser {
             let result = parse(b"Content-Length: 4\r\n\r\n{}");
             assert!(result.unwrap_err().is_incomplete());
         }
+
+        #[test]
+        fn resync_next_frame() {
+            let offset = resync(b"garbage\r\ncontent-Length: 2\r\n\r\n{}");
+            assert_eq!(offset, Some(9));
+        }
+
+        #[test]
+        fn resync_keep_tail() {
+            assert_eq!(resync(b"0123456789abcdefContent-Len"), Some(14));
+            assert_eq!(resync(b"Content-Len"), None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_all(input: &[u8]) -> Vec<String> {
+        let mut src = BytesMut::from(input);
+        let mut messages = Vec::new();
+        while let Some(message) = LspCodec.decode(&mut src).unwrap() {
+            messages.push(message);
+        }
+        messages
+    }
+
+    #[test]
+    fn decode_multiple_frames() {
+        let messages = decode_all(b"Content-Length: 2\r\n\r\n{}Content-Length: 4\r\n\r\n[42]");
+        assert_eq!(messages, vec!["{}", "[42]"]);
+    }
+
+    #[test]
+    fn decode_after_malformed_frame() {
+        let messages =
+            decode_all(b"Content-Length: foo\r\n\r\n{}Content-Length: 2\r\n\r\n{}");
+        assert_eq!(messages, vec!["{}"]);
+    }
+
+    #[test]
+    fn decode_after_garbage() {
+        let messages = decode_all(b"{\"garbage\": true}\r\nContent-Length: 2\r\n\r\n{}");
+        assert_eq!(messages, vec!["{}"]);
     }
 }
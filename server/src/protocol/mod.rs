@@ -64,6 +64,39 @@ pub struct ProductionOperationsResult {
   pub list: Vec<ProductionOperation>,
 }
 
+/// The configuration of a business host in a production.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProductionItem {
+  pub name: String,
+  pub class_name: String,
+  pub category: String,
+  pub enabled: bool,
+  pub pool_size: Option<i32>,
+  pub adapter_class: Option<String>,
+  pub comment: String,
+  pub settings: Vec<ProductionItemSetting>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProductionItemSetting {
+  pub name: String,
+  /// `Host` or `Adapter`
+  pub target: String,
+  /// The value configured for the item, if any
+  pub value: Option<String>,
+  /// The initial value of the property in the host or adapter class
+  pub default_value: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProductionExportResult {
+  pub manifest: Url,
+  pub files: Vec<Url>,
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub struct Global {
   pub name: String,
@@ -89,5 +122,64 @@ pub struct JobsResult {
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct InterSystemsConnectedParams {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub scope_uri: Option<Url>,
+  pub connected: bool,
   pub version: String,
 }
+
+#[derive(Debug, PartialEq, Eq, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InterSystemsRefreshParams {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub scope_uri: Option<Url>,
+}
+
+/// The current production and the status of its business hosts, sent whenever either changes.
+#[derive(Debug, PartialEq, Eq, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InterSystemsProductionStatusChangedParams {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub scope_uri: Option<Url>,
+  pub production: Option<Production>,
+  pub hosts: Vec<ProductionHostStatus>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProductionHostStatus {
+  pub name: String,
+  /// As shown on the production monitor, e.g. `OK`, `Error` or `Inactive`
+  pub status: String,
+}
+
+/// Asks the client for the credentials of a connection. The password is kept in memory only.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InterSystemsCredentialsParams {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub scope_uri: Option<Url>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub server_name: Option<String>,
+  pub host: String,
+  pub port: u16,
+  pub namespace: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub username: Option<String>,
+}
+
+#[derive(PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InterSystemsCredentials {
+  pub username: String,
+  pub password: String,
+}
+
+impl std::fmt::Debug for InterSystemsCredentials {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    f.debug_struct("InterSystemsCredentials")
+      .field("username", &self.username)
+      .field("password", &"<redacted>")
+      .finish()
+  }
+}
@@ -1,14 +1,47 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// How the language server talks to IRIS.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ConnBackend {
+	/// The native protocol on the superserver port
+	#[default]
+	Native,
+	/// The Atelier REST API of the web server on `port`
+	Atelier,
+}
+
+/// Certificates for the web server when `https` is set. The native backend refuses to connect
+/// with TLS enabled, since its driver only speaks plain TCP.
+#[derive(Debug, PartialEq, Eq, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnTls {
+	pub enabled: Option<bool>,
+	/// PEM file with the certificate authorities to trust instead of the built-in roots
+	pub ca_file: Option<String>,
+	pub cert_file: Option<String>,
+	pub key_file: Option<String>,
+	/// Defaults to true
+	pub verify_hostname: Option<bool>,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Conn {
 	pub active: Option<bool>,
+	/// The name of a server definition in `intersystems.servers`, which replaces
+	/// host, ports and username
+	pub server: Option<String>,
+	pub backend: Option<ConnBackend>,
 	pub host: Option<String>,
 	pub port: Option<u16>,
 	pub super_port: Option<u16>,
+	pub https: Option<bool>,
+	pub tls: Option<ConnTls>,
 	pub ns: Option<String>,
 	pub username: Option<String>,
+	/// Ignored: passwords are requested from the client with `intersystems/credentials`
 	pub password: Option<String>,
 }
 
@@ -18,8 +51,66 @@ pub struct Objectscript {
 	pub conn: Option<Conn>,
 }
 
+#[derive(Debug, PartialEq, Eq, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebServer {
+	pub scheme: Option<String>,
+	pub host: Option<String>,
+	pub port: Option<u16>,
+	pub path_prefix: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SuperServer {
+	pub host: Option<String>,
+	pub port: Option<u16>,
+}
+
+/// A named server in `intersystems.servers`, as written by the InterSystems Server Manager.
+#[derive(Debug, PartialEq, Eq, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerDefinition {
+	pub web_server: Option<WebServer>,
+	pub super_server: Option<SuperServer>,
+	pub username: Option<String>,
+	pub description: Option<String>,
+}
+
+/// Besides server definitions, `intersystems.servers` holds entries such as `"/default": "dev"`.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ServerEntry {
+	Definition(ServerDefinition),
+	Other(serde_json::Value),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Productions {
+	/// Seconds between checks of the production status; 0 turns them off
+	pub poll_interval: Option<u64>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Intersystems {
+	pub servers: Option<HashMap<String, ServerEntry>>,
+	pub productions: Option<Productions>,
+}
+
+impl Intersystems {
+	pub fn server(&self, name: &str) -> Option<&ServerDefinition> {
+		match self.servers.as_ref()?.get(name)? {
+			ServerEntry::Definition(definition) => Some(definition),
+			ServerEntry::Other(_) => None,
+		}
+	}
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Options {
 	pub objectscript: Option<Objectscript>,
+	pub intersystems: Option<Intersystems>,
 }
@@ -1,14 +1,20 @@
 use crate::{
-    config::ConfigManager, connection::ConnectionManager, protocol::*, workspace::Workspace,
+    backend::{DefaultConnector, IrisConnector, IrisError, ProductionAction},
+    config::ConfigManager,
+    connection::{ConnectionManager, WorkspaceConnections},
+    export,
+    protocol::*,
+    workspace::Workspace,
 };
 use async_trait::async_trait;
 use futures::lock::Mutex;
-use jsonrpc::{server::Result, Middleware};
+use jsonrpc::{server::Result, Error, ErrorCode, Id, Middleware, Notification, Request, Response};
 use jsonrpc_derive::{jsonrpc_method, jsonrpc_server};
 use log::trace;
 use once_cell::sync::OnceCell;
 use serde::{Deserialize, Serialize};
-use std::{mem, path::PathBuf, sync::Arc};
+use std::{future::Future, mem, path::PathBuf, sync::Arc};
+use uuid::Uuid;
 
 #[allow(dead_code)]
 pub struct InterSystemsLspServer<C> {
@@ -17,21 +23,102 @@ pub struct InterSystemsLspServer<C> {
     client_info: OnceCell<Option<ClientInfo>>,
     config_manager: OnceCell<ConfigManager<C>>,
     action_manager: ActionManager,
-    connection_manager: OnceCell<ConnectionManager<C>>,
+    connector: Arc<dyn IrisConnector>,
+    connections: OnceCell<WorkspaceConnections<C>>,
     workspace: Workspace,
+    lifecycle: Mutex<Lifecycle>,
+    exit_code: OnceCell<i32>,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum Lifecycle {
+    Uninitialized,
+    /// Until the `initialize` request with this id succeeds
+    Initializing(Id),
+    Initialized,
+    ShutDown,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct ProductionsRequestParams {
   id: Option<String>,
+  folder_uri: Option<Url>,
+}
+
+/// IRIS waits this many seconds for business hosts to finish when no timeout is given.
+const DEFAULT_PRODUCTION_TIMEOUT: u32 = 10;
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProductionActionParams {
+  id: String,
+  folder_uri: Option<Url>,
+  timeout: Option<u32>,
+  force: Option<bool>,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
-pub struct GlobalsRequestParams {}
+#[serde(rename_all = "camelCase")]
+pub struct ProductionItemParams {
+  /// The production
+  id: String,
+  /// The item in the production
+  name: String,
+  folder_uri: Option<Url>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProductionItemActionParams {
+  id: String,
+  name: String,
+  folder_uri: Option<Url>,
+  timeout: Option<u32>,
+  force: Option<bool>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProductionItemSettingParams {
+  id: String,
+  name: String,
+  folder_uri: Option<Url>,
+  /// `Host` or `Adapter`, whichever declares the setting by default
+  target: Option<String>,
+  setting: String,
+  /// The new value, or none to go back to the default
+  value: Option<String>,
+  timeout: Option<u32>,
+  force: Option<bool>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProductionExportParams {
+  id: String,
+  folder_uri: Option<Url>,
+  /// Where to write the files, `src` in the workspace folder by default
+  directory: Option<Url>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GlobalsRequestParams {
+  folder_uri: Option<Url>,
+}
 
 #[jsonrpc_server]
 impl<C: LspClient + Send + Sync + 'static> InterSystemsLspServer<C> {
     pub fn new(client: Arc<C>, current_dir: Arc<PathBuf>) -> Self {
+        Self::with_connector(client, current_dir, Arc::new(DefaultConnector))
+    }
+
+    pub fn with_connector(
+        client: Arc<C>,
+        current_dir: Arc<PathBuf>,
+        connector: Arc<dyn IrisConnector>,
+    ) -> Self {
         let workspace = Workspace::new(Arc::clone(&current_dir));
         Self {
             client: Arc::clone(&client),
@@ -39,11 +126,18 @@ impl<C: LspClient + Send + Sync + 'static> InterSystemsLspServer<C> {
             client_info: OnceCell::new(),
             config_manager: OnceCell::new(),
             action_manager: ActionManager::default(),
-            connection_manager: OnceCell::new(),
+            connector,
+            connections: OnceCell::new(),
             workspace,
+            lifecycle: Mutex::new(Lifecycle::Uninitialized),
+            exit_code: OnceCell::new(),
         }
     }
 
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code.get().copied()
+    }
+
     fn client_capabilities(&self) -> Arc<ClientCapabilities> {
         Arc::clone(
             self.client_capabilities
@@ -58,36 +152,53 @@ impl<C: LspClient + Send + Sync + 'static> InterSystemsLspServer<C> {
             .expect("initialize has not been called")
     }
 
-    fn connection_manager(&self) -> &ConnectionManager<C> {
-        self.connection_manager
+    fn connections(&self) -> &WorkspaceConnections<C> {
+        self.connections
             .get()
             .expect("initialize has not been called")
     }
 
+    async fn connection_manager(&self, folder_uri: Option<&Url>) -> Arc<ConnectionManager<C>> {
+        let folder = match folder_uri {
+            Some(uri) => self.workspace.folder_of(uri).await,
+            None => None,
+        };
+        self.connections().get(folder.as_ref()).await
+    }
+
     #[jsonrpc_method("initialize", kind = "request")]
     pub async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
         trace!("initialize: {:?}", params);
-        self.client_capabilities
-            .set(Arc::new(params.capabilities))
-            .expect("initialize was called two times");
-
-        self.client_info
-            .set(params.client_info)
-            .expect("initialize was called two times");
+        let _ = self.client_capabilities.set(Arc::new(params.capabilities));
+        let _ = self.client_info.set(params.client_info);
+        self.workspace
+            .set_folders(params.workspace_folders.unwrap_or_default())
+            .await;
 
         let _ = self.config_manager.set(ConfigManager::new(
             Arc::clone(&self.client),
             self.client_capabilities(),
         ));
 
-        let _ = self
-            .connection_manager
-            .set(ConnectionManager::new(Arc::clone(&self.client)));
+        let _ = self.connections.set(WorkspaceConnections::new(
+            Arc::clone(&self.client),
+            Arc::clone(&self.connector),
+        ));
 
         let capabilities = ServerCapabilities {
+            workspace: Some(WorkspaceCapability {
+                workspace_folders: Some(WorkspaceFolderCapability {
+                    supported: Some(true),
+                    change_notifications: Some(
+                        WorkspaceFolderCapabilityChangeNotifications::Bool(true),
+                    ),
+                }),
+            }),
             ..ServerCapabilities::default()
         };
 
+        *self.lifecycle.lock().await = Lifecycle::Initialized;
+
         Ok(InitializeResult {
             capabilities,
             server_info: Some(ServerInfo {
@@ -112,37 +223,64 @@ impl<C: LspClient + Send + Sync + 'static> InterSystemsLspServer<C> {
     #[jsonrpc_method("exit", kind = "notification")]
     pub async fn exit(&self, _params: ()) {
         trace!("exit");
-    }
+        if let Some(connections) = self.connections.get() {
+            connections.close().await;
+        }
 
-    #[jsonrpc_method("$/cancelRequest", kind = "notification")]
-    pub async fn cancel_request(&self, _params: CancelParams) {}
+        let exit_code = match *self.lifecycle.lock().await {
+            Lifecycle::ShutDown => 0,
+            _ => 1,
+        };
+        let _ = self.exit_code.set(exit_code);
+    }
 
     #[jsonrpc_method("workspace/didChangeConfiguration", kind = "notification")]
     pub async fn did_change_configuration(&self, params: DidChangeConfigurationParams) {
         let config_manager = self.config_manager();
         config_manager.push(params.settings).await;
-        let options = config_manager.get().await;
-        self.connection_manager().reparse(&options).await;
+        // Settings of workspace folders only come with a pull
+        let folders = self.workspace.folders().await;
+        config_manager.pull(&folders).await;
+        self.reparse_connections(&folders).await;
         // self.workspace.reparse(&options).await;
     }
 
+    #[jsonrpc_method("workspace/didChangeWorkspaceFolders", kind = "notification")]
+    pub async fn did_change_workspace_folders(&self, params: DidChangeWorkspaceFoldersParams) {
+        self.workspace.change_folders(params.event).await;
+        self.action_manager.push(Action::PullConfiguration).await;
+    }
+
     async fn pull_configuration(&self) -> Options {
         let config_manager = self.config_manager();
-        let has_changed = config_manager.pull().await;
+        let folders = self.workspace.folders().await;
+        let has_changed = config_manager.pull(&folders).await;
         let options = config_manager.get().await;
         if has_changed {
             // self.workspace.reparse(&options).await;
-            self.connection_manager().reparse(&options).await;
+            self.reparse_connections(&folders).await;
         }
         options
     }
 
+    async fn reparse_connections(&self, folders: &[Url]) {
+        let config_manager = self.config_manager();
+        let options = config_manager.get().await;
+        let mut folder_options = Vec::new();
+        for folder in folders {
+            let options = config_manager.get_folder(folder).await;
+            folder_options.push((folder.clone(), options));
+        }
+        self.connections().reparse(&options, folder_options).await;
+    }
+
     #[jsonrpc_method("intersystems/productions", kind = "request")]
     pub async fn productions(
         &self,
-        _params: ProductionsRequestParams,
+        params: ProductionsRequestParams,
     ) -> Result<ProductionsResult> {
-        let list = self.connection_manager().productions().await;
+        let connection_manager = self.connection_manager(params.folder_uri.as_ref()).await;
+        let list = connection_manager.productions().await?;
         Ok(ProductionsResult { list })
     }
 
@@ -151,7 +289,8 @@ impl<C: LspClient + Send + Sync + 'static> InterSystemsLspServer<C> {
         &self,
         params: ProductionsRequestParams,
     ) -> Result<ProductionServicesResult> {
-        let list = self.connection_manager().production_services(params.id.unwrap_or_default()).await;
+        let connection_manager = self.connection_manager(params.folder_uri.as_ref()).await;
+        let list = connection_manager.production_services(params.id.unwrap_or_default()).await?;
         Ok(ProductionServicesResult { list })
     }
 
@@ -160,7 +299,8 @@ impl<C: LspClient + Send + Sync + 'static> InterSystemsLspServer<C> {
         &self,
         params: ProductionsRequestParams,
     ) -> Result<ProductionOperationsResult> {
-        let list = self.connection_manager().production_operations(params.id.unwrap_or_default()).await;
+        let connection_manager = self.connection_manager(params.folder_uri.as_ref()).await;
+        let list = connection_manager.production_operations(params.id.unwrap_or_default()).await?;
         Ok(ProductionOperationsResult { list })
     }
 
@@ -169,16 +309,212 @@ impl<C: LspClient + Send + Sync + 'static> InterSystemsLspServer<C> {
         &self,
         params: ProductionsRequestParams,
     ) -> Result<ProductionProcessesResult> {
-        let list = self.connection_manager().production_processes(params.id.unwrap_or_default()).await;
+        let connection_manager = self.connection_manager(params.folder_uri.as_ref()).await;
+        let list = connection_manager.production_processes(params.id.unwrap_or_default()).await?;
         Ok(ProductionProcessesResult { list })
     }
 
+    #[jsonrpc_method("intersystems/productions/item", kind = "request")]
+    pub async fn production_item(&self, params: ProductionItemParams) -> Result<ProductionItem> {
+        let connection_manager = self.connection_manager(params.folder_uri.as_ref()).await;
+        Ok(connection_manager.production_item(params.id, params.name).await?)
+    }
+
+    #[jsonrpc_method("intersystems/productions/item/enable", kind = "request")]
+    pub async fn production_item_enable(
+        &self,
+        params: ProductionItemActionParams,
+    ) -> Result<ProductionItem> {
+        self.enable_production_item(params, true).await
+    }
+
+    #[jsonrpc_method("intersystems/productions/item/disable", kind = "request")]
+    pub async fn production_item_disable(
+        &self,
+        params: ProductionItemActionParams,
+    ) -> Result<ProductionItem> {
+        self.enable_production_item(params, false).await
+    }
+
+    #[jsonrpc_method("intersystems/productions/item/setting", kind = "request")]
+    pub async fn production_item_setting(
+        &self,
+        params: ProductionItemSettingParams,
+    ) -> Result<ProductionItem> {
+        let connection_manager = self.connection_manager(params.folder_uri.as_ref()).await;
+        let title = format!("Changing {} of {}", params.setting, params.name);
+        let timeout = params.timeout.unwrap_or(DEFAULT_PRODUCTION_TIMEOUT);
+        let force = params.force.unwrap_or_default();
+        let item = self
+            .with_progress(
+                title,
+                connection_manager.set_production_item_setting(
+                    params.id,
+                    params.name,
+                    params.target,
+                    params.setting,
+                    params.value,
+                    timeout,
+                    force,
+                ),
+            )
+            .await?;
+        Ok(item)
+    }
+
+    #[jsonrpc_method("intersystems/productions/start", kind = "request")]
+    pub async fn production_start(&self, params: ProductionActionParams) -> Result<Production> {
+        self.control_production(ProductionAction::Start, params).await
+    }
+
+    #[jsonrpc_method("intersystems/productions/stop", kind = "request")]
+    pub async fn production_stop(&self, params: ProductionActionParams) -> Result<Production> {
+        self.control_production(ProductionAction::Stop, params).await
+    }
+
+    #[jsonrpc_method("intersystems/productions/restart", kind = "request")]
+    pub async fn production_restart(&self, params: ProductionActionParams) -> Result<Production> {
+        self.control_production(ProductionAction::Restart, params).await
+    }
+
+    #[jsonrpc_method("intersystems/productions/update", kind = "request")]
+    pub async fn production_update(&self, params: ProductionActionParams) -> Result<Production> {
+        self.control_production(ProductionAction::Update, params).await
+    }
+
+    async fn control_production(
+        &self,
+        action: ProductionAction,
+        params: ProductionActionParams,
+    ) -> Result<Production> {
+        let connection_manager = self.connection_manager(params.folder_uri.as_ref()).await;
+        let title = format!("{} {}", action.title(), params.id);
+        let timeout = params.timeout.unwrap_or(DEFAULT_PRODUCTION_TIMEOUT);
+        let force = params.force.unwrap_or_default();
+        let production = self
+            .with_progress(
+                title,
+                connection_manager.control_production(action, params.id, timeout, force),
+            )
+            .await?;
+        Ok(production)
+    }
+
+    async fn enable_production_item(
+        &self,
+        params: ProductionItemActionParams,
+        enabled: bool,
+    ) -> Result<ProductionItem> {
+        let connection_manager = self.connection_manager(params.folder_uri.as_ref()).await;
+        let verb = if enabled { "Enabling" } else { "Disabling" };
+        let title = format!("{} {}", verb, params.name);
+        let timeout = params.timeout.unwrap_or(DEFAULT_PRODUCTION_TIMEOUT);
+        let force = params.force.unwrap_or_default();
+        let item = self
+            .with_progress(
+                title,
+                connection_manager.enable_production_item(
+                    params.id,
+                    params.name,
+                    enabled,
+                    timeout,
+                    force,
+                ),
+            )
+            .await?;
+        Ok(item)
+    }
+
+    // Reports `task` as work done progress, if the client supports it.
+    async fn with_progress<T>(
+        &self,
+        title: String,
+        task: impl Future<Output = std::result::Result<T, IrisError>>,
+    ) -> std::result::Result<T, IrisError> {
+        let token = ProgressToken::String(Uuid::new_v4().to_string());
+        let report = self.client_capabilities().has_work_done_progress_support()
+            && self
+                .client
+                .work_done_progress_create(WorkDoneProgressCreateParams {
+                    token: token.clone(),
+                })
+                .await
+                .is_ok();
+        if !report {
+            return task.await;
+        }
+
+        let begin = WorkDoneProgress::Begin(WorkDoneProgressBegin {
+            title,
+            cancellable: Some(false),
+            message: None,
+            percentage: None,
+        });
+        self.report_progress(token.clone(), begin).await;
+        let result = task.await;
+        let message = match &result {
+            Ok(_) => None,
+            Err(error) => Some(error.to_string()),
+        };
+        self.report_progress(token, WorkDoneProgress::End(WorkDoneProgressEnd { message }))
+            .await;
+        result
+    }
+
+    async fn report_progress(&self, token: ProgressToken, progress: WorkDoneProgress) {
+        let params = ProgressParams {
+            token,
+            value: ProgressParamsValue::WorkDone(progress),
+        };
+        self.client.progress(params).await;
+    }
+
+    #[jsonrpc_method("intersystems/productions/export", kind = "request")]
+    pub async fn production_export(
+        &self,
+        params: ProductionExportParams,
+    ) -> Result<ProductionExportResult> {
+        let directory = match params.directory {
+            Some(directory) => directory.to_file_path().ok(),
+            None => self
+                .export_folder(params.folder_uri.as_ref())
+                .await
+                .and_then(|folder| folder.to_file_path().ok())
+                .map(|folder| folder.join("src")),
+        };
+        let directory = directory.ok_or_else(|| {
+            Error::invalid_request_error("No local directory to export to".to_owned())
+        })?;
+
+        let connection_manager = self.connection_manager(params.folder_uri.as_ref()).await;
+        let sources = connection_manager.production_sources(params.id).await?;
+        let (manifest, files) = export::write_production(&directory, &sources)
+            .await
+            .map_err(|why| Error::internal_error(format!("Failed to write the export: {}", why)))?;
+        let to_url = |path| Url::from_file_path(path).ok();
+        Ok(ProductionExportResult {
+            manifest: to_url(manifest).ok_or_else(|| {
+                Error::internal_error("The manifest has no file URL".to_owned())
+            })?,
+            files: files.into_iter().filter_map(to_url).collect(),
+        })
+    }
+
+    // The workspace folder of `folder_uri`, or the first one
+    async fn export_folder(&self, folder_uri: Option<&Url>) -> Option<Url> {
+        match folder_uri {
+            Some(uri) => self.workspace.folder_of(uri).await,
+            None => self.workspace.folders().await.into_iter().next(),
+        }
+    }
+
     #[jsonrpc_method("intersystems/globals", kind = "request")]
     pub async fn globals(
         &self,
-        _params: GlobalsRequestParams,
+        params: GlobalsRequestParams,
     ) -> Result<GlobalsResult> {
-        let list = self.connection_manager().globals().await;
+        let connection_manager = self.connection_manager(params.folder_uri.as_ref()).await;
+        let list = connection_manager.globals().await?;
         Ok(GlobalsResult { list })
     }
 }
@@ -187,6 +523,55 @@ impl<C: LspClient + Send + Sync + 'static> InterSystemsLspServer<C> {
 impl<C: LspClient + Send + Sync + 'static> Middleware for InterSystemsLspServer<C> {
     async fn before_message(&self) {}
 
+    async fn before_request(&self, request: &Request) -> std::result::Result<(), Error> {
+        let mut lifecycle = self.lifecycle.lock().await;
+        match (&*lifecycle, request.method.as_str()) {
+            (Lifecycle::Uninitialized, "initialize") => {
+                *lifecycle = Lifecycle::Initializing(request.id.clone());
+                Ok(())
+            }
+            (Lifecycle::Uninitialized, _) | (Lifecycle::Initializing(_), _) => {
+                Err(Error::server_not_initialized_error())
+            }
+            (Lifecycle::Initialized, "initialize") => Err(Error::invalid_request_error(
+                "initialize has already been called".to_owned(),
+            )),
+            (Lifecycle::Initialized, "shutdown") => {
+                *lifecycle = Lifecycle::ShutDown;
+                Ok(())
+            }
+            (Lifecycle::Initialized, _) => Ok(()),
+            (Lifecycle::ShutDown, _) => Err(Error::invalid_request_error(
+                "shutdown has already been requested".to_owned(),
+            )),
+        }
+    }
+
+    async fn after_request(&self, method: &str, response: &Response) {
+        if method != "initialize" || response.error.is_none() {
+            return;
+        }
+
+        // A failed or cancelled initialize can be retried
+        let mut lifecycle = self.lifecycle.lock().await;
+        if matches!(&*lifecycle, Lifecycle::Initializing(id) if Some(id) == response.id.as_ref()) {
+            *lifecycle = Lifecycle::Uninitialized;
+        }
+    }
+
+    async fn before_notification(&self, notification: &Notification) -> bool {
+        notification.method == "exit"
+            || *self.lifecycle.lock().await == Lifecycle::Initialized
+    }
+
+    async fn notification_failed(&self, method: &str, error: Error) {
+        let params = LogMessageParams {
+            typ: MessageType::Error,
+            message: failure_message(method, &error),
+        };
+        self.client.log_message(params).await;
+    }
+
     async fn after_message(&self) {
         for action in self.action_manager.take().await {
             match action {
@@ -202,6 +587,25 @@ impl<C: LspClient + Send + Sync + 'static> Middleware for InterSystemsLspServer<
     }
 }
 
+impl From<IrisError> for Error {
+    fn from(error: IrisError) -> Self {
+        let data = serde_json::to_value(&error).unwrap_or_default();
+        Error::new(ErrorCode::Custom(error.code()), error.to_string()).with_data(data)
+    }
+}
+
+/// Describes a failed notification, with the detail from `data` only if there is one.
+fn failure_message(method: &str, error: &Error) -> String {
+    let message = format!("Failed to handle \"{}\": {}", method, error.message);
+    let detail = match &error.data {
+        None | Some(serde_json::Value::Null) => return message,
+        Some(serde_json::Value::String(detail)) if detail.is_empty() => return message,
+        Some(serde_json::Value::String(detail)) => detail.clone(),
+        Some(data) => data.to_string(),
+    };
+    format!("{} ({})", message, detail)
+}
+
 #[derive(Debug, PartialEq, Clone)]
 enum Action {
     RegisterCapabilities,
@@ -224,3 +628,29 @@ impl ActionManager {
         mem::replace(&mut *actions, Vec::new())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn failure_message_without_detail() {
+        let error = Error::deserialize_error();
+        let message = failure_message("workspace/didChangeConfiguration", &error);
+        assert_eq!(
+            message,
+            format!("Failed to handle \"workspace/didChangeConfiguration\": {}", error.message)
+        );
+        let empty = failure_message("exit", &error.clone().with_data(json!("")));
+        assert!(!empty.ends_with("()"));
+    }
+
+    #[test]
+    fn failure_message_with_detail() {
+        let error = Error::deserialize_error().with_data(json!("invalid type: integer `42`"));
+        assert!(failure_message("exit", &error).ends_with(" (invalid type: integer `42`)"));
+        let error = Error::deserialize_error().with_data(json!({ "field": "settings" }));
+        assert!(failure_message("exit", &error).ends_with(r#" ({"field":"settings"})"#));
+    }
+}
@@ -0,0 +1,523 @@
+use crate::{
+    backend::IrisConnector, protocol::InterSystemsLspClient, server::InterSystemsLspServer,
+};
+use chashmap::CHashMap;
+use futures::{channel::mpsc, prelude::*};
+use jsonrpc::{Id, MessageHandler, Response, ResponseHandler};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{self, BufRead, Write},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc as std_mpsc, Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Direction {
+    Incoming,
+    Outgoing,
+}
+
+impl Direction {
+    fn reverse(self) -> Self {
+        match self {
+            Self::Incoming => Self::Outgoing,
+            Self::Outgoing => Self::Incoming,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TraceEntry {
+    /// The client connection, numbered from 1 in the order they were accepted
+    #[serde(default)]
+    pub session: u64,
+
+    /// Milliseconds since the Unix epoch
+    pub timestamp: u64,
+    pub direction: Direction,
+
+    /// Milliseconds between a request and this response
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<f64>,
+
+    pub message: Value,
+}
+
+enum TraceCommand {
+    Write(TraceEntry),
+    Flush(std_mpsc::Sender<()>),
+}
+
+/// Writes every message that passes the transport as one JSON line. The writing happens
+/// on a thread of its own, so recording a message never blocks the runtime.
+#[derive(Debug)]
+pub struct TraceRecorder {
+    commands: Mutex<std_mpsc::Sender<TraceCommand>>,
+    sessions: AtomicU64,
+}
+
+impl TraceRecorder {
+    /// Starts the writer thread. It returns `output` once the recorder and all of its
+    /// sessions are dropped.
+    pub fn new<W: Write + Send + 'static>(output: W) -> (Self, thread::JoinHandle<W>) {
+        let (commands, receiver) = std_mpsc::channel();
+        let writer = thread::spawn(move || {
+            let mut output = output;
+            for command in receiver {
+                match command {
+                    TraceCommand::Write(entry) => {
+                        let result = serde_json::to_writer(&mut output, &entry)
+                            .map_err(io::Error::from)
+                            .and_then(|_| writeln!(output))
+                            .and_then(|_| output.flush());
+                        if let Err(why) = result {
+                            warn!("Failed to write trace entry: {}", why);
+                        }
+                    }
+                    TraceCommand::Flush(done) => {
+                        let _ = done.send(());
+                    }
+                }
+            }
+            output
+        });
+        let recorder = Self {
+            commands: Mutex::new(commands),
+            sessions: AtomicU64::new(0),
+        };
+        (recorder, writer)
+    }
+
+    /// A recorder for the messages of the next client connection.
+    pub fn session(&self) -> SessionRecorder {
+        SessionRecorder {
+            session: self.sessions.fetch_add(1, Ordering::Relaxed) + 1,
+            commands: Mutex::new(self.commands.lock().unwrap().clone()),
+            requests: CHashMap::new(),
+        }
+    }
+
+    /// Waits until every entry recorded so far is written.
+    pub fn flush(&self) {
+        let (done, written) = std_mpsc::channel();
+        let sent = self
+            .commands
+            .lock()
+            .unwrap()
+            .send(TraceCommand::Flush(done));
+        if sent.is_ok() {
+            let _ = written.recv();
+        }
+    }
+}
+
+/// Records the messages of one client connection, tagged with its session number,
+/// and the latency of its requests.
+#[derive(Debug)]
+pub struct SessionRecorder {
+    session: u64,
+    commands: Mutex<std_mpsc::Sender<TraceCommand>>,
+    requests: CHashMap<(Direction, Id), Instant>,
+}
+
+impl SessionRecorder {
+    pub fn record(&self, direction: Direction, json: &str) {
+        let message = serde_json::from_str(json).unwrap_or_else(|_| Value::String(json.into()));
+        let now = Instant::now();
+        let mut latency_ms = None;
+        for item in message
+            .as_array()
+            .map(Vec::as_slice)
+            .unwrap_or_else(|| std::slice::from_ref(&message))
+        {
+            let id = match item.get("id").cloned().map(serde_json::from_value::<Id>) {
+                Some(Ok(id)) => id,
+                _ => continue,
+            };
+
+            if item.get("method").is_some() {
+                self.requests.insert((direction, id), now);
+            } else if let Some(start) = self.requests.remove(&(direction.reverse(), id)) {
+                latency_ms = Some(now.duration_since(start).as_secs_f64() * 1000.0);
+            }
+        }
+
+        let entry = TraceEntry {
+            session: self.session,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|time| time.as_millis() as u64)
+                .unwrap_or_default(),
+            direction,
+            latency_ms,
+            message,
+        };
+
+        if self
+            .commands
+            .lock()
+            .unwrap()
+            .send(TraceCommand::Write(entry))
+            .is_err()
+        {
+            warn!("Failed to write trace entry: the writer has stopped");
+        }
+    }
+}
+
+pub fn read_trace<R: BufRead>(input: R) -> io::Result<Vec<TraceEntry>> {
+    let mut entries = Vec::new();
+    for line in input.lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            entries.push(serde_json::from_str(&line)?);
+        }
+    }
+    Ok(entries)
+}
+
+/// Splits a trace into the entries of each session, in the order the sessions began.
+pub fn sessions(entries: Vec<TraceEntry>) -> Vec<(u64, Vec<TraceEntry>)> {
+    let mut sessions: Vec<(u64, Vec<TraceEntry>)> = Vec::new();
+    for entry in entries {
+        match sessions
+            .iter_mut()
+            .find(|(session, _)| *session == entry.session)
+        {
+            Some((_, entries)) => entries.push(entry),
+            None => sessions.push((entry.session, vec![entry])),
+        }
+    }
+    sessions
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ReplayOutcome {
+    Match,
+    Mismatch { expected: Value, actual: Value },
+    Missing { expected: Value },
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ReplayResult {
+    pub id: Id,
+    pub method: String,
+    pub outcome: ReplayOutcome,
+}
+
+/// Feeds the incoming messages of one recorded session into a fresh server and compares
+/// its responses with the recorded ones. Requests from the server to the client are
+/// answered with the recorded responses to the same method, in recorded order.
+/// The server talks to IRIS through `connector`, usually a `FakeIris` with fixtures.
+pub async fn replay(
+    entries: Vec<TraceEntry>,
+    connector: Arc<dyn IrisConnector>,
+    timeout: Duration,
+) -> Vec<ReplayResult> {
+    let mut requests = Vec::new();
+    let mut expected = HashMap::new();
+    let mut methods_by_outgoing_id = HashMap::new();
+    let mut client_responses: HashMap<String, VecDeque<Value>> = HashMap::new();
+    let mut input = Vec::new();
+    for entry in entries {
+        for message in flatten(entry.message) {
+            let id = message
+                .get("id")
+                .cloned()
+                .and_then(|id| serde_json::from_value::<Id>(id).ok());
+            let method = message
+                .get("method")
+                .and_then(Value::as_str)
+                .map(str::to_owned);
+            match (entry.direction, id, method) {
+                (Direction::Incoming, Some(id), None) => {
+                    if let Some(method) = methods_by_outgoing_id.get(&id) {
+                        client_responses
+                            .entry(String::clone(method))
+                            .or_default()
+                            .push_back(message);
+                    }
+                }
+                (Direction::Incoming, id, Some(method)) => {
+                    if let Some(id) = id {
+                        requests.push((id, method));
+                    }
+                    input.push(message);
+                }
+                (Direction::Outgoing, Some(id), None) => {
+                    expected.insert(id, message);
+                }
+                (Direction::Outgoing, Some(id), Some(method)) => {
+                    methods_by_outgoing_id.insert(id, method);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let (output_tx, mut output_rx) = mpsc::channel(0);
+    let client = Arc::new(InterSystemsLspClient::new(output_tx.clone()));
+    let server = Arc::new(InterSystemsLspServer::with_connector(
+        Arc::clone(&client),
+        Arc::new(PathBuf::new()),
+        connector,
+    ));
+    let mut handler = MessageHandler::new(server, Arc::clone(&client), output_tx);
+
+    let pending_ids: Vec<Id> = requests.iter().map(|(id, _)| id.clone()).collect();
+    let collector = tokio::spawn(async move {
+        let mut actual = HashMap::new();
+        while actual.len() < pending_ids.len() {
+            let json = match tokio::time::timeout(timeout, output_rx.next()).await {
+                Ok(Some(json)) => json,
+                _ => break,
+            };
+
+            for message in flatten(serde_json::from_str(&json).unwrap_or_default()) {
+                let id = message
+                    .get("id")
+                    .cloned()
+                    .and_then(|id| serde_json::from_value::<Id>(id).ok());
+                let method = message.get("method").and_then(Value::as_str);
+                match (id, method) {
+                    (Some(id), Some(method)) => {
+                        let recorded = client_responses
+                            .get_mut(method)
+                            .and_then(VecDeque::pop_front)
+                            .and_then(|message| serde_json::from_value::<Response>(message).ok());
+                        let response = match recorded {
+                            Some(response) => Response {
+                                id: Some(id),
+                                ..response
+                            },
+                            None => Response::result(Value::Null, id),
+                        };
+                        client.handle(response).await;
+                    }
+                    (Some(id), None) => {
+                        actual.insert(id, message);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        actual
+    });
+
+    for message in input {
+        handler.handle(&message.to_string()).await;
+    }
+    let mut actual = collector.await.unwrap_or_default();
+
+    requests
+        .into_iter()
+        .filter_map(|(id, method)| {
+            let expected = expected.remove(&id)?;
+            let outcome = match actual.remove(&id) {
+                Some(actual) if response_body(&actual) == response_body(&expected) => {
+                    ReplayOutcome::Match
+                }
+                Some(actual) => ReplayOutcome::Mismatch { expected, actual },
+                None => ReplayOutcome::Missing { expected },
+            };
+            Some(ReplayResult {
+                id,
+                method,
+                outcome,
+            })
+        })
+        .collect()
+}
+
+fn flatten(message: Value) -> Vec<Value> {
+    match message {
+        Value::Array(messages) => messages,
+        message => vec![message],
+    }
+}
+
+fn response_body(response: &Value) -> (Option<&Value>, Option<&Value>) {
+    (response.get("result"), response.get("error"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::FakeIris;
+    use serde_json::json;
+
+    fn entry(direction: Direction, message: Value) -> TraceEntry {
+        TraceEntry {
+            session: 1,
+            timestamp: 0,
+            direction,
+            latency_ms: None,
+            message,
+        }
+    }
+
+    fn initialize_request(id: u64) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": "initialize",
+            "params": { "processId": null, "rootUri": null, "capabilities": {} }
+        })
+    }
+
+    /// Records with `f` and returns what the writer thread wrote.
+    fn recorded(f: impl FnOnce(&TraceRecorder)) -> Vec<TraceEntry> {
+        let (recorder, writer) = TraceRecorder::new(Vec::new());
+        f(&recorder);
+        drop(recorder);
+        read_trace(writer.join().unwrap().as_slice()).unwrap()
+    }
+
+    #[test]
+    fn record_latency() {
+        let entries = recorded(|recorder| {
+            let session = recorder.session();
+            session.record(Direction::Incoming, &initialize_request(1).to_string());
+            session.record(
+                Direction::Outgoing,
+                r#"{"jsonrpc":"2.0","result":null,"id":1}"#,
+            );
+        });
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].direction, Direction::Incoming);
+        assert_eq!(entries[0].latency_ms, None);
+        assert_eq!(entries[1].direction, Direction::Outgoing);
+        assert!(entries[1].latency_ms.is_some());
+    }
+
+    #[test]
+    fn record_invalid_json() {
+        let entries = recorded(|recorder| recorder.session().record(Direction::Incoming, "{"));
+        assert_eq!(entries[0].message, json!("{"));
+    }
+
+    #[test]
+    fn sessions_are_kept_apart() {
+        let entries = recorded(|recorder| {
+            let first = recorder.session();
+            let second = recorder.session();
+            first.record(Direction::Incoming, &initialize_request(1).to_string());
+            second.record(Direction::Incoming, &initialize_request(1).to_string());
+            second.record(
+                Direction::Outgoing,
+                r#"{"jsonrpc":"2.0","result":null,"id":1}"#,
+            );
+            first.record(
+                Direction::Outgoing,
+                r#"{"jsonrpc":"2.0","result":null,"id":1}"#,
+            );
+        });
+
+        let sessions = sessions(entries);
+        assert_eq!(
+            sessions
+                .iter()
+                .map(|(session, entries)| (*session, entries.len()))
+                .collect::<Vec<_>>(),
+            vec![(1, 2), (2, 2)]
+        );
+        assert!(sessions[0].1[1].latency_ms.is_some());
+        assert!(sessions[1].1[1].latency_ms.is_some());
+    }
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Write::write(&mut *self.0.lock().unwrap(), buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn flush_waits_for_writer() {
+        let buffer = SharedBuffer::default();
+        let (recorder, _writer) = TraceRecorder::new(buffer.clone());
+        let session = recorder.session();
+        session.record(Direction::Incoming, "{}");
+        recorder.flush();
+
+        let entries = read_trace(buffer.0.lock().unwrap().as_slice()).unwrap();
+        assert_eq!(entries.len(), 1);
+    }
+
+    #[test]
+    fn read_trace_without_sessions() {
+        let line = r#"{"timestamp":0,"direction":"incoming","message":{}}"#;
+        assert_eq!(read_trace(line.as_bytes()).unwrap()[0].session, 0);
+    }
+
+    #[tokio::test]
+    async fn replay_matching_session() {
+        let response = initialize_response().await;
+        let entries = vec![
+            entry(Direction::Incoming, initialize_request(1)),
+            entry(Direction::Outgoing, response),
+        ];
+
+        let results = replay(
+            entries,
+            Arc::new(FakeIris::default()),
+            Duration::from_secs(5),
+        )
+        .await;
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].method, "initialize");
+        assert_eq!(results[0].outcome, ReplayOutcome::Match);
+    }
+
+    #[tokio::test]
+    async fn replay_changed_response() {
+        let expected = json!({ "jsonrpc": "2.0", "id": 1, "result": { "capabilities": {} } });
+        let entries = vec![
+            entry(Direction::Incoming, initialize_request(1)),
+            entry(Direction::Outgoing, expected.clone()),
+        ];
+
+        let results = replay(
+            entries,
+            Arc::new(FakeIris::default()),
+            Duration::from_secs(5),
+        )
+        .await;
+        match &results[0].outcome {
+            ReplayOutcome::Mismatch {
+                expected: mismatch, ..
+            } => assert_eq!(mismatch, &expected),
+            outcome => panic!("unexpected outcome: {:?}", outcome),
+        }
+    }
+
+    async fn initialize_response() -> Value {
+        let (output_tx, mut output_rx) = mpsc::channel(1);
+        let client = Arc::new(InterSystemsLspClient::new(output_tx.clone()));
+        let server = Arc::new(InterSystemsLspServer::with_connector(
+            Arc::clone(&client),
+            Arc::new(PathBuf::new()),
+            Arc::new(FakeIris::default()),
+        ));
+        let mut handler = MessageHandler::new(server, client, output_tx);
+        handler.handle(&initialize_request(1).to_string()).await;
+        serde_json::from_str(&output_rx.next().await.unwrap()).unwrap()
+    }
+}
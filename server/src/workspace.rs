@@ -1,17 +1,106 @@
-use crate::protocol::Options;
+use crate::protocol::{Options, Url, WorkspaceFolder, WorkspaceFoldersChangeEvent};
+use futures::lock::Mutex;
 use std::{path::PathBuf, sync::Arc};
 
 pub struct Workspace {
     _current_dir: Arc<PathBuf>,
+    folders: Mutex<Vec<WorkspaceFolder>>,
 }
 
 impl Workspace {
     pub fn new(_current_dir: Arc<PathBuf>) -> Self {
         Self {
             _current_dir,
+            folders: Mutex::default(),
         }
     }
 
-    pub async fn reparse(&self, _options: &Options) {
+    pub async fn reparse(&self, _options: &Options) {}
+
+    pub async fn folders(&self) -> Vec<Url> {
+        let folders = self.folders.lock().await;
+        folders.iter().map(|folder| folder.uri.clone()).collect()
+    }
+
+    pub async fn set_folders(&self, folders: Vec<WorkspaceFolder>) {
+        *self.folders.lock().await = folders;
+    }
+
+    pub async fn change_folders(&self, event: WorkspaceFoldersChangeEvent) {
+        let mut folders = self.folders.lock().await;
+        folders.retain(|folder| !event.removed.contains(folder));
+        folders.extend(event.added);
+    }
+
+    /// Finds the workspace folder that contains `uri`, preferring the innermost one.
+    pub async fn folder_of(&self, uri: &Url) -> Option<Url> {
+        let folders = self.folders.lock().await;
+        folders
+            .iter()
+            .map(|folder| &folder.uri)
+            .filter(|folder| {
+                let folder = folder.as_str().trim_end_matches('/');
+                uri.as_str() == folder || uri.as_str().starts_with(&format!("{}/", folder))
+            })
+            .max_by_key(|folder| folder.as_str().len())
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn folder(uri: &str) -> WorkspaceFolder {
+        WorkspaceFolder {
+            uri: Url::parse(uri).unwrap(),
+            name: String::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn folder_of_innermost() {
+        let workspace = Workspace::new(Arc::default());
+        workspace
+            .set_folders(vec![
+                folder("file:///repo"),
+                folder("file:///repo/nested/"),
+                folder("file:///repository"),
+            ])
+            .await;
+
+        let cases = vec![
+            ("file:///repo/src/Demo.cls", Some("file:///repo")),
+            ("file:///repo/nested/Demo.cls", Some("file:///repo/nested/")),
+            ("file:///repository", Some("file:///repository")),
+            ("file:///other", None),
+        ];
+        for (uri, expected) in cases {
+            let folder = workspace.folder_of(&Url::parse(uri).unwrap()).await;
+            assert_eq!(folder, expected.map(|folder| Url::parse(folder).unwrap()));
+        }
+    }
+
+    #[tokio::test]
+    async fn change_folders() {
+        let workspace = Workspace::new(Arc::default());
+        workspace
+            .set_folders(vec![folder("file:///a"), folder("file:///b")])
+            .await;
+
+        workspace
+            .change_folders(WorkspaceFoldersChangeEvent {
+                added: vec![folder("file:///c")],
+                removed: vec![folder("file:///a")],
+            })
+            .await;
+
+        let folders: Vec<_> = workspace
+            .folders()
+            .await
+            .iter()
+            .map(Url::to_string)
+            .collect();
+        assert_eq!(folders, vec!["file:///b", "file:///c"]);
     }
 }
@@ -0,0 +1,290 @@
+use serde_json::{json, Value};
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+    thread,
+    time::Duration,
+};
+
+struct ServerProcess(Child);
+
+impl Drop for ServerProcess {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn spawn_server(args: &[&str]) -> ServerProcess {
+    let child = Command::new(env!("CARGO_BIN_EXE_iris-ls"))
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("failed to start iris-ls");
+    ServerProcess(child)
+}
+
+fn spawn_stdio_server() -> (ServerProcess, ChildStdin, BufReader<ChildStdout>) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_iris-ls"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("failed to start iris-ls");
+    let stdin = child.stdin.take().unwrap();
+    let stdout = BufReader::new(child.stdout.take().unwrap());
+    (ServerProcess(child), stdin, stdout)
+}
+
+fn free_port() -> u16 {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    listener.local_addr().unwrap().port()
+}
+
+fn connect(port: u16) -> TcpStream {
+    for _ in 0..50 {
+        if let Ok(stream) = TcpStream::connect((Ipv4Addr::LOCALHOST, port)) {
+            return stream;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    panic!("iris-ls is not listening on port {}", port);
+}
+
+fn send(stream: &mut impl Write, message: Value) {
+    let content = message.to_string();
+    write!(stream, "Content-Length: {}\r\n\r\n{}", content.len(), content).unwrap();
+    stream.flush().unwrap();
+}
+
+fn receive(reader: &mut impl BufRead) -> Value {
+    let mut length = 0;
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse().unwrap();
+        }
+    }
+
+    let mut content = vec![0; length];
+    reader.read_exact(&mut content).unwrap();
+    serde_json::from_slice(&content).unwrap()
+}
+
+fn initialize_request(id: u64) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "method": "initialize",
+        "params": {
+            "processId": null,
+            "rootUri": null,
+            "capabilities": {}
+        }
+    })
+}
+
+fn request(id: u64, method: &str) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": null })
+}
+
+fn notification(method: &str) -> Value {
+    json!({ "jsonrpc": "2.0", "method": method, "params": null })
+}
+
+fn wait_for_exit(server: &mut ServerProcess) -> Option<i32> {
+    for _ in 0..50 {
+        if let Some(status) = server.0.try_wait().unwrap() {
+            return status.code();
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    panic!("iris-ls did not exit");
+}
+
+#[test]
+fn request_before_initialize() {
+    let (_server, mut stdin, mut stdout) = spawn_stdio_server();
+    send(&mut stdin, request(1, "intersystems/globals"));
+
+    let response = receive(&mut stdout);
+    assert_eq!(response["id"], 1);
+    assert_eq!(response["error"]["code"], -32002);
+}
+
+#[test]
+fn initialize_twice() {
+    let (_server, mut stdin, mut stdout) = spawn_stdio_server();
+    send(&mut stdin, initialize_request(1));
+    assert!(receive(&mut stdout).get("error").is_none());
+
+    send(&mut stdin, initialize_request(2));
+    let response = receive(&mut stdout);
+    assert_eq!(response["id"], 2);
+    assert_eq!(response["error"]["code"], -32600);
+}
+
+#[test]
+fn initialize_again_after_invalid_params() {
+    let (_server, mut stdin, mut stdout) = spawn_stdio_server();
+    let mut invalid = initialize_request(1);
+    invalid["params"] = json!({ "capabilities": 42 });
+    send(&mut stdin, invalid);
+    let response = receive(&mut stdout);
+    assert_eq!(response["id"], 1);
+    assert_eq!(response["error"]["code"], -32602);
+
+    send(&mut stdin, initialize_request(2));
+    let response = receive(&mut stdout);
+    assert_eq!(response["id"], 2);
+    assert!(response.get("error").is_none());
+}
+
+#[test]
+fn request_after_shutdown() {
+    let (_server, mut stdin, mut stdout) = spawn_stdio_server();
+    send(&mut stdin, initialize_request(1));
+    receive(&mut stdout);
+    send(&mut stdin, request(2, "shutdown"));
+    assert!(receive(&mut stdout).get("error").is_none());
+
+    send(&mut stdin, request(3, "intersystems/globals"));
+    let response = receive(&mut stdout);
+    assert_eq!(response["id"], 3);
+    assert_eq!(response["error"]["code"], -32600);
+}
+
+#[test]
+fn exit_after_shutdown() {
+    let (mut server, mut stdin, mut stdout) = spawn_stdio_server();
+    send(&mut stdin, initialize_request(1));
+    receive(&mut stdout);
+    send(&mut stdin, request(2, "shutdown"));
+    receive(&mut stdout);
+    send(&mut stdin, notification("exit"));
+
+    assert_eq!(wait_for_exit(&mut server), Some(0));
+}
+
+#[test]
+fn exit_without_shutdown() {
+    let (mut server, mut stdin, mut stdout) = spawn_stdio_server();
+    send(&mut stdin, initialize_request(1));
+    receive(&mut stdout);
+    send(&mut stdin, notification("exit"));
+
+    assert_eq!(wait_for_exit(&mut server), Some(1));
+}
+
+#[test]
+fn messages_without_params() {
+    let (mut server, mut stdin, mut stdout) = spawn_stdio_server();
+    send(&mut stdin, initialize_request(1));
+    receive(&mut stdout);
+
+    send(
+        &mut stdin,
+        json!({ "jsonrpc": "2.0", "id": 2, "method": "intersystems/productions" }),
+    );
+    let response = receive(&mut stdout);
+    assert_eq!(response["id"], 2);
+    assert_eq!(response["error"]["code"], 1000);
+    assert_eq!(response["error"]["data"]["kind"], "notConfigured");
+
+    send(&mut stdin, json!({ "jsonrpc": "2.0", "id": 3, "method": "shutdown" }));
+    let response = receive(&mut stdout);
+    assert_eq!(response["id"], 3);
+    assert!(response.get("error").is_none());
+
+    send(&mut stdin, json!({ "jsonrpc": "2.0", "method": "exit" }));
+    assert_eq!(wait_for_exit(&mut server), Some(0));
+}
+
+#[test]
+fn notification_with_invalid_params() {
+    let (_server, mut stdin, mut stdout) = spawn_stdio_server();
+    send(&mut stdin, initialize_request(1));
+    receive(&mut stdout);
+    send(
+        &mut stdin,
+        json!({
+            "jsonrpc": "2.0",
+            "method": "workspace/didChangeConfiguration",
+            "params": 42
+        }),
+    );
+
+    let message = receive(&mut stdout);
+    assert_eq!(message["method"], "window/logMessage");
+    assert_eq!(message["params"]["type"], 1);
+    let text = message["params"]["message"].as_str().unwrap();
+    assert!(text.contains("workspace/didChangeConfiguration"));
+    assert!(text.contains("invalid type: integer `42`"));
+
+    send(&mut stdin, request(2, "shutdown"));
+    assert_eq!(receive(&mut stdout)["id"], 2);
+}
+
+#[test]
+fn initialize_over_tcp() {
+    let port = free_port();
+    let _server = spawn_server(&["--listen", &port.to_string()]);
+
+    let mut stream = connect(port);
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    send(&mut stream, initialize_request(1));
+
+    let response = receive(&mut reader);
+    assert_eq!(response["id"], 1);
+    assert_eq!(
+        response["result"]["serverInfo"]["name"],
+        "InterSystems Language Server"
+    );
+}
+
+#[test]
+fn separate_sessions_per_connection() {
+    let port = free_port();
+    let _server = spawn_server(&["--listen", &port.to_string()]);
+
+    for id in 1..=2 {
+        let mut stream = connect(port);
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        send(&mut stream, initialize_request(id));
+
+        let response = receive(&mut reader);
+        assert_eq!(response["id"], id);
+        assert!(response.get("error").is_none());
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn initialize_over_unix_socket() {
+    use std::os::unix::net::UnixStream;
+
+    let path = std::env::temp_dir().join(format!("iris-ls-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let _server = spawn_server(&["--socket", path.to_str().unwrap()]);
+
+    let mut stream = (0..50)
+        .find_map(|_| {
+            UnixStream::connect(&path).ok().or_else(|| {
+                thread::sleep(Duration::from_millis(100));
+                None
+            })
+        })
+        .expect("iris-ls is not listening on the socket");
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    send(&mut stream, initialize_request(1));
+
+    let response = receive(&mut reader);
+    assert_eq!(response["id"], 1);
+    let _ = std::fs::remove_file(&path);
+}